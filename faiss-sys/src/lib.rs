@@ -12,6 +12,9 @@ mod bindings;
 #[cfg(not(feature = "gpu"))]
 pub use bindings::*;
 
+/// The version of these bindings.
+pub const BINDINGS_VERSION: &str = env!("CARGO_PKG_VERSION");
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,362 @@
+//! Exhaustive search over borrowed vectors, without building an index.
+//!
+//! The functions in this module compare each query vector against every
+//! database vector, like a flat index would, but read the database directly
+//! from the given slice instead of copying it into an index first. This is
+//! mostly useful for computing ground truth, or for one-off searches.
+//!
+//! The labels in the results are the positions of the vectors in the
+//! database. An optional [`IdSelector`] restricts the search to the
+//! database vectors whose position it selects.
+//!
+//! Distances are computed block by block, with the block sizes and the
+//! BLAS threshold set in the [`config`] module. As in Faiss, L2 distances
+//! are computed with BLAS once there are enough queries, whereas inner
+//! products are always computed with SIMD kernels, as the C API of Faiss
+//! provides no BLAS kernel for them.
+//!
+//! [`config`]: ../config/index.html
+//! [`IdSelector`]: ../selector/struct.IdSelector.html
+//!
+//! # Examples
+//!
+//! ```
+//! use faiss::brute_force::knn_l2;
+//! use faiss::Idx;
+//! # fn run() -> faiss::error::Result<()> {
+//! let database = [0., 0., 1., 1., 5., 5.];
+//! let result = knn_l2(&[0.9, 0.9], &database, 2, 2, None)?;
+//! assert_eq!(result.labels, vec![Idx::new(1), Idx::new(0)]);
+//! # Ok(())
+//! # }
+//! # run().unwrap();
+//! ```
+
+use crate::config;
+use crate::distance::num_vectors;
+use crate::error::Result;
+use crate::faiss_try;
+use crate::index::{Idx, RangeSearchResult, SearchResult};
+use crate::selector::IdSelector;
+use crate::MetricType;
+use faiss_sys::*;
+use std::cmp::Ordering;
+use std::ptr;
+
+/// Find the `k` nearest neighbors of each query vector among the database
+/// vectors, by squared L2 distance.
+///
+/// Queries with fewer than `k` (selected) database vectors are padded with
+/// [`Idx::none()`] labels and a distance of `f32::MAX`.
+///
+/// # Error
+///
+/// Returns [`Error::BadLength`] if either slice does not hold a whole
+/// number of vectors of dimensionality `d`.
+///
+/// [`Idx::none()`]: ../index/struct.Idx.html#method.none
+/// [`Error::BadLength`]: ../error/enum.Error.html#variant.BadLength
+pub fn knn_l2(
+    queries: &[f32],
+    database: &[f32],
+    d: u32,
+    k: usize,
+    selector: Option<&IdSelector>,
+) -> Result<SearchResult> {
+    knn(queries, database, d, k, selector, MetricType::L2)
+}
+
+/// Find the `k` database vectors with the largest inner product with each
+/// query vector.
+///
+/// Queries with fewer than `k` (selected) database vectors are padded with
+/// [`Idx::none()`] labels and a distance of `f32::MIN`.
+///
+/// # Error
+///
+/// Returns [`Error::BadLength`] if either slice does not hold a whole
+/// number of vectors of dimensionality `d`.
+///
+/// [`Idx::none()`]: ../index/struct.Idx.html#method.none
+/// [`Error::BadLength`]: ../error/enum.Error.html#variant.BadLength
+pub fn knn_inner_product(
+    queries: &[f32],
+    database: &[f32],
+    d: u32,
+    k: usize,
+    selector: Option<&IdSelector>,
+) -> Result<SearchResult> {
+    knn(queries, database, d, k, selector, MetricType::InnerProduct)
+}
+
+/// Find all database vectors within a squared L2 distance strictly lower
+/// than `radius` of each query vector.
+///
+/// As with indexes, the results of each query are not sorted.
+///
+/// # Error
+///
+/// Returns [`Error::BadLength`] if either slice does not hold a whole
+/// number of vectors of dimensionality `d`.
+///
+/// [`Error::BadLength`]: ../error/enum.Error.html#variant.BadLength
+pub fn range_l2(
+    queries: &[f32],
+    database: &[f32],
+    d: u32,
+    radius: f32,
+    selector: Option<&IdSelector>,
+) -> Result<RangeSearchResult> {
+    let d = d as usize;
+    let nq = num_vectors(queries.len(), d)?;
+    num_vectors(database.len(), d)?;
+
+    let mut hits: Vec<Vec<Candidate>> = vec![Vec::new(); nq];
+    for_each_block(queries, database, d, MetricType::L2, |i0, j0, nbb, dis| {
+        for (row, hits_q) in dis.chunks_exact(nbb).zip(&mut hits[i0..]) {
+            hits_q.extend(
+                row.iter()
+                    .enumerate()
+                    .map(|(j, &dis)| (dis, j0 + j))
+                    .filter(|&(dis, j)| dis < radius && is_selected(selector, j)),
+            );
+        }
+    });
+
+    unsafe {
+        let mut inner = ptr::null_mut();
+        faiss_try(faiss_RangeSearchResult_new(&mut inner, nq as idx_t))?;
+        let result = RangeSearchResult { inner };
+
+        // lims holds the number of results per query until allocation
+        let mut lims = ptr::null_mut();
+        faiss_RangeSearchResult_lims(inner, &mut lims);
+        for (i, hits_q) in hits.iter().enumerate() {
+            *lims.add(i) = hits_q.len();
+        }
+        faiss_try(faiss_RangeSearchResult_do_allocation(inner))?;
+
+        let mut labels = ptr::null_mut();
+        let mut distances = ptr::null_mut();
+        faiss_RangeSearchResult_labels(inner, &mut labels, &mut distances);
+        for (i, &(dis, j)) in hits.iter().flatten().enumerate() {
+            *distances.add(i) = dis;
+            *labels.add(i) = j as idx_t;
+        }
+        Ok(result)
+    }
+}
+
+fn is_selected(selector: Option<&IdSelector>, j: usize) -> bool {
+    match selector {
+        Some(sel) => sel.is_member(Idx::new(j as u64)),
+        None => true,
+    }
+}
+
+/// A database vector's distance to the query and its position.
+///
+/// Candidates are ordered best first, with ties broken by position.
+type Candidate = (f32, usize);
+
+fn knn(
+    queries: &[f32],
+    database: &[f32],
+    d: u32,
+    k: usize,
+    selector: Option<&IdSelector>,
+    metric: MetricType,
+) -> Result<SearchResult> {
+    let d = d as usize;
+    let nq = num_vectors(queries.len(), d)?;
+    num_vectors(database.len(), d)?;
+
+    let (worst, cmp): (f32, fn(&Candidate, &Candidate) -> Ordering) = match metric {
+        MetricType::L2 => (f32::MAX, |a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1))),
+        MetricType::InnerProduct => (f32::MIN, |a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1))),
+    };
+
+    // the best candidates so far of each query, at most `k` between blocks
+    let mut candidates: Vec<Vec<Candidate>> = vec![Vec::new(); nq];
+    for_each_block(queries, database, d, metric, |i0, j0, nbb, dis| {
+        for (row, candidates_q) in dis.chunks_exact(nbb).zip(&mut candidates[i0..]) {
+            candidates_q.extend(
+                row.iter()
+                    .enumerate()
+                    .map(|(j, &dis)| (dis, j0 + j))
+                    .filter(|&(_, j)| is_selected(selector, j)),
+            );
+            if candidates_q.len() > k {
+                candidates_q.select_nth_unstable_by(k, cmp);
+                candidates_q.truncate(k);
+            }
+        }
+    });
+
+    let mut distances = Vec::with_capacity(nq * k);
+    let mut labels = Vec::with_capacity(nq * k);
+    for mut candidates_q in candidates {
+        candidates_q.sort_unstable_by(cmp);
+        for &(dis, j) in &candidates_q {
+            distances.push(dis);
+            labels.push(Idx::new(j as u64));
+        }
+        for _ in candidates_q.len()..k {
+            distances.push(worst);
+            labels.push(Idx::none());
+        }
+    }
+    Ok(SearchResult { distances, labels })
+}
+
+/// Compute the distances between each block of query vectors and each
+/// block of database vectors, reusing a single buffer for all of them.
+///
+/// For each pair of blocks, `f` is given the positions of their first
+/// query and database vectors, the number of database vectors in the
+/// block, and the distances from each query of the block to them.
+/// Both slices must hold whole numbers of vectors of a nonzero `d`.
+fn for_each_block<F>(queries: &[f32], database: &[f32], d: usize, metric: MetricType, mut f: F)
+where
+    F: FnMut(usize, usize, usize, &[f32]),
+{
+    let nq = queries.len() / d;
+    let nb = database.len() / d;
+    let use_blas = metric == MetricType::L2 && nq >= config::blas_threshold();
+    let qbs = config::blas_query_bs().clamp(1, nq.max(1));
+    let dbs = config::blas_database_bs().clamp(1, nb.max(1));
+
+    let mut dis = Vec::with_capacity(qbs * dbs);
+    for (qi, query_block) in queries.chunks(qbs * d).enumerate() {
+        let nqb = query_block.len() / d;
+        for (bi, database_block) in database.chunks(dbs * d).enumerate() {
+            let nbb = database_block.len() / d;
+            dis.clear();
+            dis.resize(nqb * nbb, 0_f32);
+            unsafe {
+                if use_blas {
+                    faiss_pairwise_L2sqr_with_defaults(
+                        d as i64,
+                        nqb as i64,
+                        query_block.as_ptr(),
+                        nbb as i64,
+                        database_block.as_ptr(),
+                        dis.as_mut_ptr(),
+                    );
+                } else {
+                    for (query, row) in query_block.chunks_exact(d).zip(dis.chunks_exact_mut(nbb)) {
+                        let kernel = match metric {
+                            MetricType::L2 => faiss_fvec_L2sqr_ny,
+                            MetricType::InnerProduct => faiss_fvec_inner_products_ny,
+                        };
+                        kernel(
+                            row.as_mut_ptr(),
+                            query.as_ptr(),
+                            database_block.as_ptr(),
+                            d,
+                            nbb,
+                        );
+                    }
+                }
+            }
+            f(qi * qbs, bi * dbs, nbb, &dis);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    const DATABASE: [f32; 8] = [0., 0., 1., 1., 5., 5., -1., 0.];
+
+    #[test]
+    fn knn_l2_search() {
+        let queries = [0.9, 0.9, 10., 10.];
+        let result = knn_l2(&queries, &DATABASE, 2, 2, None).unwrap();
+        assert_eq!(
+            result.labels,
+            vec![Idx::new(1), Idx::new(0), Idx::new(2), Idx::new(1)]
+        );
+        assert!((result.distances[0] - 0.02).abs() < 1e-5);
+        assert_eq!(result.distances[2], 50.);
+
+        let result = knn_l2(&queries[..2], &DATABASE, 2, 6, None).unwrap();
+        assert_eq!(result.labels[4..], [Idx::none(), Idx::none()]);
+        assert_eq!(result.distances[5], f32::MAX);
+
+        assert!(matches!(
+            knn_l2(&queries[..3], &DATABASE, 2, 2, None),
+            Err(Error::BadLength)
+        ));
+    }
+
+    #[test]
+    fn knn_inner_product_search() {
+        let result = knn_inner_product(&[1., 0.], &DATABASE, 2, 3, None).unwrap();
+        assert_eq!(result.labels, vec![Idx::new(2), Idx::new(1), Idx::new(0)]);
+        assert_eq!(result.distances, vec![5., 1., 0.]);
+    }
+
+    #[test]
+    fn knn_with_selector() {
+        let sel = IdSelector::batch(&[Idx::new(0), Idx::new(3)]).unwrap();
+        let result = knn_l2(&[0.9, 0.9], &DATABASE, 2, 3, Some(&sel)).unwrap();
+        assert_eq!(result.labels, vec![Idx::new(0), Idx::new(3), Idx::none()]);
+    }
+
+    #[test]
+    fn range_l2_search() {
+        let queries = [0., 0., 5., 5.];
+        let result = range_l2(&queries, &DATABASE, 2, 2.5, None).unwrap();
+        assert_eq!(result.lims(), &[0, 3, 4]);
+        let (distances, labels) = result.distance_and_labels();
+        assert_eq!(
+            labels,
+            &[Idx::new(0), Idx::new(1), Idx::new(3), Idx::new(2)]
+        );
+        assert_eq!(distances, &[0., 2., 1., 0.]);
+
+        let sel = IdSelector::range(Idx::new(1), Idx::new(3)).unwrap();
+        let result = range_l2(&queries, &DATABASE, 2, 2.5, Some(&sel)).unwrap();
+        assert_eq!(result.lims(), &[0, 1, 2]);
+    }
+
+    #[test]
+    fn knn_many_blocks() {
+        // enough vectors to span several blocks and reach BLAS,
+        // with small integer values so that all distances are exact
+        let d = 4;
+        let vector = |i: usize| (0..d).map(move |c| ((i * 7 + c * 3) % 11) as f32);
+        let nq = config::blas_threshold() + 3;
+        let nb = config::blas_database_bs() * 2 + 5;
+        let queries: Vec<f32> = (0..nq).flat_map(vector).collect();
+        let database: Vec<f32> = (nq..nq + nb).flat_map(vector).collect();
+
+        let k = 3;
+        let result = knn_l2(&queries, &database, d as u32, k, None).unwrap();
+        for (query, labels) in queries.chunks(d).zip(result.labels.chunks(k)) {
+            let mut expected: Vec<Candidate> = database
+                .chunks(d)
+                .map(|x| query.iter().zip(x).map(|(a, b)| (a - b) * (a - b)).sum())
+                .zip(0..)
+                .collect();
+            expected.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+            let expected: Vec<Idx> = expected[..k]
+                .iter()
+                .map(|&(_, j)| Idx::new(j as u64))
+                .collect();
+            assert_eq!(labels, &expected[..]);
+        }
+
+        let result = knn_inner_product(&queries, &database, d as u32, 1, None).unwrap();
+        for (query, &dis) in queries.chunks(d).zip(&result.distances) {
+            let best = database
+                .chunks(d)
+                .map(|x| query.iter().zip(x).map(|(a, b)| a * b).sum::<f32>())
+                .fold(f32::MIN, f32::max);
+            assert_eq!(dis, best);
+        }
+    }
+}
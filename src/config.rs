@@ -0,0 +1,261 @@
+//! Run-time configuration of the Faiss library.
+//!
+//! This module controls when Faiss switches to BLAS for exhaustive distance
+//! computations and with which block sizes, as well as the number of
+//! OpenMP threads used by parallel operations.
+//!
+//! The BLAS settings are global to the process, and changing them while
+//! other threads are searching is not recommended. The OpenMP thread count,
+//! on the other hand, applies to operations started from the calling
+//! thread, so [`with_omp_threads`] can bound the CPU usage of each request
+//! in a multi-threaded server.
+//!
+//! The OpenMP runtime is looked up among the symbols already loaded in the
+//! process, which fails on non-Unix platforms and may fail when Faiss is
+//! linked statically. The OpenMP functions of this module then silently do
+//! nothing; use [`is_omp_available`] to tell whether they take effect.
+//!
+//! [`with_omp_threads`]: fn.with_omp_threads.html
+//! [`is_omp_available`]: fn.is_omp_available.html
+//!
+//! # Examples
+//!
+//! ```
+//! use faiss::{config, FlatIndex, Index};
+//! # fn run() -> faiss::error::Result<()> {
+//! let mut index = FlatIndex::new_l2(4)?;
+//! index.add(&[0.; 4 * 16])?;
+//! let result = config::with_omp_threads(1, || index.search(&[0.; 4], 1))?;
+//! assert_eq!(result.labels.len(), 1);
+//! # Ok(())
+//! # }
+//! # run().unwrap();
+//! ```
+
+use faiss_sys::*;
+use std::convert::TryFrom;
+use std::os::raw::c_int;
+
+fn to_c_int(value: usize) -> c_int {
+    c_int::try_from(value).unwrap_or(c_int::MAX)
+}
+
+/// Obtain the number of query vectors above which exhaustive distance
+/// computations are done with BLAS.
+pub fn blas_threshold() -> usize {
+    unsafe { faiss_get_distance_compute_blas_threshold() as usize }
+}
+
+/// Set the number of query vectors above which exhaustive distance
+/// computations are done with BLAS.
+pub fn set_blas_threshold(value: usize) {
+    unsafe { faiss_set_distance_compute_blas_threshold(to_c_int(value)) }
+}
+
+/// Obtain the number of query vectors per block in BLAS distance
+/// computations.
+pub fn blas_query_bs() -> usize {
+    unsafe { faiss_get_distance_compute_blas_query_bs() as usize }
+}
+
+/// Set the number of query vectors per block in BLAS distance
+/// computations.
+pub fn set_blas_query_bs(value: usize) {
+    unsafe { faiss_set_distance_compute_blas_query_bs(to_c_int(value)) }
+}
+
+/// Obtain the number of database vectors per block in BLAS distance
+/// computations.
+pub fn blas_database_bs() -> usize {
+    unsafe { faiss_get_distance_compute_blas_database_bs() as usize }
+}
+
+/// Set the number of database vectors per block in BLAS distance
+/// computations.
+pub fn set_blas_database_bs(value: usize) {
+    unsafe { faiss_set_distance_compute_blas_database_bs(to_c_int(value)) }
+}
+
+/// Obtain the number of results per query above which they are collected
+/// in a reservoir rather than a heap.
+pub fn min_k_reservoir() -> usize {
+    unsafe { faiss_get_distance_compute_min_k_reservoir() as usize }
+}
+
+/// Set the number of results per query above which they are collected
+/// in a reservoir rather than a heap.
+pub fn set_min_k_reservoir(value: usize) {
+    unsafe { faiss_set_distance_compute_min_k_reservoir(to_c_int(value)) }
+}
+
+/// Access to the OpenMP runtime loaded along with Faiss.
+///
+/// The runtime functions are looked up when first needed rather than
+/// linked against, so that this works regardless of which OpenMP
+/// implementation Faiss was built with (if any).
+mod omp {
+    use std::os::raw::c_int;
+
+    pub(super) struct Runtime {
+        pub(super) get_max_threads: unsafe extern "C" fn() -> c_int,
+        pub(super) set_num_threads: unsafe extern "C" fn(c_int),
+    }
+
+    #[cfg(unix)]
+    pub(super) fn runtime() -> Option<&'static Runtime> {
+        use std::mem;
+        use std::os::raw::{c_char, c_void};
+        use std::sync::OnceLock;
+
+        extern "C" {
+            fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+        }
+
+        #[cfg(any(
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "freebsd",
+            target_os = "dragonfly"
+        ))]
+        const RTLD_DEFAULT: *mut c_void = -2_isize as *mut c_void;
+        #[cfg(not(any(
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "freebsd",
+            target_os = "dragonfly"
+        )))]
+        const RTLD_DEFAULT: *mut c_void = std::ptr::null_mut();
+
+        static RUNTIME: OnceLock<Option<Runtime>> = OnceLock::new();
+        RUNTIME
+            .get_or_init(|| unsafe {
+                let get = dlsym(RTLD_DEFAULT, b"omp_get_max_threads\0".as_ptr() as *const _);
+                let set = dlsym(RTLD_DEFAULT, b"omp_set_num_threads\0".as_ptr() as *const _);
+                if get.is_null() || set.is_null() {
+                    return None;
+                }
+                Some(Runtime {
+                    get_max_threads: mem::transmute::<*mut c_void, unsafe extern "C" fn() -> c_int>(
+                        get,
+                    ),
+                    set_num_threads: mem::transmute::<*mut c_void, unsafe extern "C" fn(c_int)>(
+                        set,
+                    ),
+                })
+            })
+            .as_ref()
+    }
+
+    #[cfg(not(unix))]
+    pub(super) fn runtime() -> Option<&'static Runtime> {
+        None
+    }
+}
+
+/// Check whether an OpenMP runtime is available to Faiss.
+///
+/// Without one, Faiss operations are single-threaded, and the OpenMP
+/// functions of this module behave accordingly.
+pub fn is_omp_available() -> bool {
+    omp::runtime().is_some()
+}
+
+/// Obtain the maximum number of OpenMP threads used by Faiss operations
+/// started from the calling thread.
+///
+/// Returns 1 if no OpenMP runtime is available (see [`is_omp_available`]),
+/// whatever the number of threads actually used by Faiss.
+pub fn omp_max_threads() -> usize {
+    match omp::runtime() {
+        Some(rt) => unsafe { (rt.get_max_threads)() as usize },
+        None => 1,
+    }
+}
+
+/// Set the number of OpenMP threads used by Faiss operations started from
+/// the calling thread.
+///
+/// A value of 0 is treated as 1, since OpenMP requires a positive number
+/// of threads. This silently does nothing if no OpenMP runtime is
+/// available (see [`is_omp_available`]).
+pub fn set_omp_num_threads(n: usize) {
+    if let Some(rt) = omp::runtime() {
+        unsafe { (rt.set_num_threads)(to_c_int(n.max(1))) }
+    }
+}
+
+/// Restores the OpenMP thread count of the calling thread when dropped.
+struct OmpThreadsGuard {
+    previous: usize,
+}
+
+impl Drop for OmpThreadsGuard {
+    fn drop(&mut self) {
+        set_omp_num_threads(self.previous);
+    }
+}
+
+/// Call `f` with the number of OpenMP threads used by Faiss operations
+/// started from the calling thread set to `n`.
+///
+/// The previous thread count is restored afterwards,
+/// even if `f` panics. As with [`set_omp_num_threads`],
+/// a value of 0 is treated as 1, and the thread count is silently left
+/// unchanged if no OpenMP runtime is available (see [`is_omp_available`]).
+pub fn with_omp_threads<F, R>(n: usize, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let _guard = OmpThreadsGuard {
+        previous: omp_max_threads(),
+    };
+    set_omp_num_threads(n);
+    f()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blas_settings() {
+        let threshold = blas_threshold();
+        let query_bs = blas_query_bs();
+        let database_bs = blas_database_bs();
+        let reservoir = min_k_reservoir();
+
+        set_blas_threshold(threshold + 1);
+        set_blas_query_bs(query_bs + 1);
+        set_blas_database_bs(database_bs + 1);
+        set_min_k_reservoir(reservoir + 1);
+        assert_eq!(blas_threshold(), threshold + 1);
+        assert_eq!(blas_query_bs(), query_bs + 1);
+        assert_eq!(blas_database_bs(), database_bs + 1);
+        assert_eq!(min_k_reservoir(), reservoir + 1);
+
+        set_blas_threshold(threshold);
+        set_blas_query_bs(query_bs);
+        set_blas_database_bs(database_bs);
+        set_min_k_reservoir(reservoir);
+        assert_eq!(blas_threshold(), threshold);
+    }
+
+    #[test]
+    fn omp_threads_scope() {
+        let before = omp_max_threads();
+        let inside = with_omp_threads(2, omp_max_threads);
+        if is_omp_available() {
+            assert_eq!(inside, 2);
+        } else {
+            assert_eq!(inside, 1);
+        }
+        assert_eq!(omp_max_threads(), before);
+
+        assert_eq!(with_omp_threads(0, omp_max_threads), 1);
+        assert_eq!(omp_max_threads(), before);
+
+        let r = std::panic::catch_unwind(|| with_omp_threads(3, || panic!("oops")));
+        assert!(r.is_err());
+        assert_eq!(omp_max_threads(), before);
+    }
+}
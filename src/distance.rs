@@ -0,0 +1,191 @@
+//! Exact distance computations between vectors.
+//!
+//! These functions call the native Faiss kernels (using BLAS or SIMD
+//! instructions where available) directly over slices of vectors, without
+//! building an index. As elsewhere in this crate, a set of vectors of
+//! dimensionality `d` is a contiguous slice of `n * d` elements.
+//!
+//! # Examples
+//!
+//! ```
+//! use faiss::distance;
+//! # fn run() -> faiss::error::Result<()> {
+//! let queries = [0., 0., 1., 1.];
+//! let database = [1., 0., 3., 4., 0., 0.];
+//! let dis = distance::pairwise_l2_sqr(2, &queries, &database)?;
+//! assert_eq!(dis, vec![1., 25., 0., 1., 13., 2.]);
+//! # Ok(())
+//! # }
+//! # run().unwrap();
+//! ```
+
+use crate::error::{Error, Result};
+use faiss_sys::*;
+
+/// Obtain the number of `d`-dimensional vectors in a slice of `len`
+/// elements, or an error if `len` is not a multiple of a nonzero `d`.
+pub(crate) fn num_vectors(len: usize, d: usize) -> Result<usize> {
+    match len.checked_div(d) {
+        Some(n) if n * d == len => Ok(n),
+        _ => Err(Error::BadLength),
+    }
+}
+
+/// Compute the squared L2 distances between all pairs of query and
+/// database vectors of dimensionality `d`.
+///
+/// The distance between query `i` and database vector `j`
+/// is at position `i * nb + j` of the resulting matrix.
+///
+/// # Error
+///
+/// Returns [`Error::BadLength`] if either slice does not hold a whole
+/// number of vectors.
+pub fn pairwise_l2_sqr(d: u32, queries: &[f32], database: &[f32]) -> Result<Vec<f32>> {
+    let nq = num_vectors(queries.len(), d as usize)?;
+    let nb = num_vectors(database.len(), d as usize)?;
+    let mut dis = vec![0_f32; nq * nb];
+    if !dis.is_empty() {
+        unsafe {
+            faiss_pairwise_L2sqr_with_defaults(
+                d as i64,
+                nq as i64,
+                queries.as_ptr(),
+                nb as i64,
+                database.as_ptr(),
+                dis.as_mut_ptr(),
+            );
+        }
+    }
+    Ok(dis)
+}
+
+/// Compute the inner products between the vector `x`
+/// and each of the vectors in `ys`.
+///
+/// The dimensionality of the vectors is given by the length of `x`.
+///
+/// # Error
+///
+/// Returns [`Error::BadLength`] if `ys` does not hold a whole number of
+/// vectors.
+pub fn inner_products_ny(x: &[f32], ys: &[f32]) -> Result<Vec<f32>> {
+    let ny = num_vectors(ys.len(), x.len())?;
+    let mut ip = vec![0_f32; ny];
+    unsafe {
+        faiss_fvec_inner_products_ny(ip.as_mut_ptr(), x.as_ptr(), ys.as_ptr(), x.len(), ny);
+    }
+    Ok(ip)
+}
+
+/// Compute the squared L2 distances between the vector `x`
+/// and each of the vectors in `ys`.
+///
+/// The dimensionality of the vectors is given by the length of `x`.
+///
+/// # Error
+///
+/// Returns [`Error::BadLength`] if `ys` does not hold a whole number of
+/// vectors.
+pub fn l2_sqr_ny(x: &[f32], ys: &[f32]) -> Result<Vec<f32>> {
+    let ny = num_vectors(ys.len(), x.len())?;
+    let mut dis = vec![0_f32; ny];
+    unsafe {
+        faiss_fvec_L2sqr_ny(dis.as_mut_ptr(), x.as_ptr(), ys.as_ptr(), x.len(), ny);
+    }
+    Ok(dis)
+}
+
+/// Compute the squared L2 norm of the vector `x`.
+pub fn norm_l2_sqr(x: &[f32]) -> f32 {
+    unsafe { faiss_fvec_norm_L2sqr(x.as_ptr(), x.len()) }
+}
+
+/// Compute the L2 norms of the vectors of dimensionality `d` in `x`.
+///
+/// # Error
+///
+/// Returns [`Error::BadLength`] if `x` does not hold a whole number of
+/// vectors.
+pub fn norms_l2(d: u32, x: &[f32]) -> Result<Vec<f32>> {
+    let nx = num_vectors(x.len(), d as usize)?;
+    let mut norms = vec![0_f32; nx];
+    unsafe {
+        faiss_fvec_norms_L2(norms.as_mut_ptr(), x.as_ptr(), d as usize, nx);
+    }
+    Ok(norms)
+}
+
+/// Compute the squared L2 norms of the vectors of dimensionality `d`
+/// in `x`.
+///
+/// # Error
+///
+/// Returns [`Error::BadLength`] if `x` does not hold a whole number of
+/// vectors.
+pub fn norms_l2_sqr(d: u32, x: &[f32]) -> Result<Vec<f32>> {
+    let nx = num_vectors(x.len(), d as usize)?;
+    let mut norms = vec![0_f32; nx];
+    unsafe {
+        faiss_fvec_norms_L2sqr(norms.as_mut_ptr(), x.as_ptr(), d as usize, nx);
+    }
+    Ok(norms)
+}
+
+/// Normalize the vectors of dimensionality `d` in `x` in place,
+/// so that each of them has an L2 norm of 1.
+///
+/// Vectors with a norm of zero are left unchanged.
+///
+/// # Error
+///
+/// Returns [`Error::BadLength`] if `x` does not hold a whole number of
+/// vectors.
+pub fn renorm_l2(d: u32, x: &mut [f32]) -> Result<()> {
+    let nx = num_vectors(x.len(), d as usize)?;
+    unsafe {
+        faiss_fvec_renorm_L2(d as usize, nx, x.as_mut_ptr());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairwise() {
+        let queries = [0., 0., 1., 1.];
+        let database = [1., 0., 3., 4., 0., 0.];
+        let dis = pairwise_l2_sqr(2, &queries, &database).unwrap();
+        assert_eq!(dis, vec![1., 25., 0., 1., 13., 2.]);
+
+        assert!(pairwise_l2_sqr(2, &queries, &database[..5]).is_err());
+        assert!(pairwise_l2_sqr(0, &queries, &database).is_err());
+        assert_eq!(pairwise_l2_sqr(2, &[], &database).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn one_to_many() {
+        let x = [1., 2.];
+        let ys = [1., 0., 0., 1., -1., -1.];
+        assert_eq!(inner_products_ny(&x, &ys).unwrap(), vec![1., 2., -3.]);
+        assert_eq!(l2_sqr_ny(&x, &ys).unwrap(), vec![4., 2., 13.]);
+        assert!(l2_sqr_ny(&x, &ys[..3]).is_err());
+    }
+
+    #[test]
+    fn norms() {
+        let x = [3., 4., 0., 0., 1., 0.];
+        assert_eq!(norm_l2_sqr(&x[..2]), 25.);
+        assert_eq!(norms_l2(2, &x).unwrap(), vec![5., 0., 1.]);
+        assert_eq!(norms_l2_sqr(2, &x).unwrap(), vec![25., 0., 1.]);
+        assert!(norms_l2(4, &x).is_err());
+
+        let mut x = x;
+        renorm_l2(2, &mut x).unwrap();
+        assert!((x[0] - 0.6).abs() < 1e-6);
+        assert!((x[1] - 0.8).abs() < 1e-6);
+        assert_eq!(&x[2..], &[0., 0., 1., 0.]);
+    }
+}
@@ -4,7 +4,9 @@ use faiss_sys::*;
 use std::error::Error as StdError;
 use std::ffi::CStr;
 use std::fmt;
+use std::io;
 use std::os::raw::c_int;
+use std::sync::Arc;
 
 /// Type alias for results of functions in this crate.
 pub type Result<T> = ::std::result::Result<T, Error>;
@@ -20,10 +22,22 @@ pub enum Error {
     IndexDescription,
     /// Invalid file path.
     BadFilePath,
+    /// Invalid combination of I/O flags.
+    BadIoFlags,
     /// Invalid parameter name of index.
     ParameterName,
     /// The number of GPU resources and devices do not match.
     GpuResourcesMatch,
+    /// The indexes involved in an operation have incompatible parameters.
+    IndexMismatch,
+    /// The length of an input slice does not match the expected length.
+    BadLength,
+    /// An I/O error occurred while reading or writing an index.
+    Io(IoError),
+    /// Serialized index data failed verification.
+    Corrupt(String),
+    /// An argument is outside of its valid range.
+    BadArgument,
 }
 
 impl fmt::Display for Error {
@@ -33,20 +47,26 @@ impl fmt::Display for Error {
             Error::BadCast => fmt.write_str("Invalid index type cast"),
             Error::IndexDescription => fmt.write_str("Invalid index description"),
             Error::BadFilePath => fmt.write_str("Invalid file path"),
+            Error::BadIoFlags => fmt.write_str("Invalid combination of I/O flags"),
             Error::ParameterName => fmt.write_str("Invalid parameter name of index"),
             Error::GpuResourcesMatch => {
                 fmt.write_str("Number of GPU resources and devices do not match")
             }
+            Error::IndexMismatch => fmt.write_str("Indexes have incompatible parameters"),
+            Error::BadLength => fmt.write_str("Invalid input length"),
+            Error::Io(e) => write!(fmt, "I/O error: {}", e.0),
+            Error::Corrupt(msg) => write!(fmt, "Corrupt index data: {}", msg),
+            Error::BadArgument => fmt.write_str("Argument out of range"),
         }
     }
 }
 
 impl StdError for Error {
     fn source(&self) -> Option<&(dyn StdError + 'static)> {
-        if let Error::Native(err) = self {
-            Some(err)
-        } else {
-            None
+        match self {
+            Error::Native(err) => Some(err),
+            Error::Io(err) => Some(err.get_ref()),
+            _ => None,
         }
     }
 }
@@ -110,3 +130,40 @@ impl From<NativeError> for Error {
         Error::Native(e)
     }
 }
+
+/// An I/O error raised by a Rust reader or writer.
+///
+/// The underlying [`io::Error`] is shared so that [`Error`] remains cloneable.
+#[derive(Debug, Clone)]
+pub struct IoError(Arc<io::Error>);
+
+impl IoError {
+    /// Getter for the underlying I/O error.
+    pub fn get_ref(&self) -> &io::Error {
+        &self.0
+    }
+
+    /// Getter for the kind of the underlying I/O error.
+    pub fn kind(&self) -> io::ErrorKind {
+        self.0.kind()
+    }
+}
+
+impl PartialEq for IoError {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+            || (self.0.kind() == other.0.kind() && self.0.to_string() == other.0.to_string())
+    }
+}
+
+impl fmt::Display for IoError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, fmt)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(IoError(Arc::new(e)))
+    }
+}
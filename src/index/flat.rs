@@ -349,6 +349,54 @@ mod tests {
         assert!(distances.iter().all(|x| *x > 0.));
     }
 
+    #[test]
+    fn flat_index_reconstruct() {
+        let mut index = FlatIndexImpl::new_l2(D).unwrap();
+        let some_data = &[
+            7.5_f32, -7.5, 7.5, -7.5, 7.5, 7.5, 7.5, 7.5, -1., 1., 1., 1., 1., 1., 1., -1., 0., 0.,
+            0., 1., 1., 0., 0., -1., 100., 100., 100., 100., -100., 100., 100., 100., 120., 100.,
+            100., 105., -100., 100., 100., 105.,
+        ];
+        index.add(some_data).unwrap();
+
+        let v = index.reconstruct(Idx::new(2)).unwrap();
+        assert_eq!(&v[..], &some_data[16..24]);
+        let v = index.reconstruct_n(Idx::new(0), 5).unwrap();
+        assert_eq!(&v[..], &some_data[..]);
+        let v = index
+            .reconstruct_batch(&[Idx::new(3), Idx::new(1)])
+            .unwrap();
+        assert_eq!(&v[..8], &some_data[24..32]);
+        assert_eq!(&v[8..], &some_data[8..16]);
+
+        assert!(index.reconstruct(Idx::new(5)).is_err());
+    }
+
+    #[test]
+    fn flat_index_compute_residual() {
+        let mut index = FlatIndexImpl::new_l2(D).unwrap();
+        let some_data = &[
+            7.5_f32, -7.5, 7.5, -7.5, 7.5, 7.5, 7.5, 7.5, -1., 1., 1., 1., 1., 1., 1., -1., 0., 0.,
+            0., 1., 1., 0., 0., -1., 100., 100., 100., 100., -100., 100., 100., 100., 120., 100.,
+            100., 105., -100., 100., 100., 105.,
+        ];
+        index.add(some_data).unwrap();
+
+        let x = [1.; D as usize];
+        let residual = index.compute_residual(&x, Idx::new(2)).unwrap();
+        let expected: Vec<f32> = some_data[16..24].iter().map(|v| 1. - v).collect();
+        assert_eq!(residual, expected);
+
+        let xs = [1.; 2 * D as usize];
+        // flat index can be used behind an immutable ref
+        let residuals =
+            ConcurrentIndex::compute_residual_n(&index, &xs, &[Idx::new(2), Idx::new(0)]).unwrap();
+        assert_eq!(residuals.len(), 2 * D as usize);
+        assert_eq!(&residuals[..8], &expected[..]);
+        let expected: Vec<f32> = some_data[..8].iter().map(|v| 1. - v).collect();
+        assert_eq!(&residuals[8..], &expected[..]);
+    }
+
     #[test]
     fn index_transition() {
         let index = {
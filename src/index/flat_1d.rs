@@ -0,0 +1,209 @@
+//! Interface and implementation to the Flat1D index type.
+//!
+//! This index is an exhaustive index for 1-dimensional vectors
+//! (i.e. scalars) under the L2 metric. It keeps the indexed values sorted,
+//! which enables nearest-value lookups in logarithmic time.
+
+use super::*;
+
+use crate::error::{Error, Result};
+use crate::faiss_try;
+use std::mem;
+use std::os::raw::c_int;
+use std::ptr;
+
+/// Native implementation of a flat index for 1-dimensional vectors.
+///
+/// The index maintains a sorting permutation of the indexed values, which
+/// must be up to date when searching. With continuous updates, this is done
+/// on each addition. Otherwise, [`update_permutation`] must be called after
+/// adding vectors and before searching.
+///
+/// [`update_permutation`]: #method.update_permutation
+#[derive(Debug)]
+pub struct Flat1DIndex {
+    inner: *mut FaissIndexFlat1D,
+}
+
+unsafe impl Send for Flat1DIndex {}
+unsafe impl Sync for Flat1DIndex {}
+
+impl CpuIndex for Flat1DIndex {}
+
+impl Drop for Flat1DIndex {
+    fn drop(&mut self) {
+        unsafe {
+            faiss_IndexFlat1D_free(self.inner);
+        }
+    }
+}
+
+impl Flat1DIndex {
+    /// Create a new flat index for 1-dimensional vectors.
+    ///
+    /// If `continuous_update` is true, the sorting permutation is
+    /// updated on each addition.
+    pub fn new(continuous_update: bool) -> Result<Self> {
+        unsafe {
+            let mut inner = ptr::null_mut();
+            faiss_try(faiss_IndexFlat1D_new_with(
+                &mut inner,
+                c_int::from(continuous_update),
+            ))?;
+            Ok(Flat1DIndex { inner })
+        }
+    }
+
+    /// Update the sorting permutation of the indexed values.
+    pub fn update_permutation(&mut self) -> Result<()> {
+        unsafe {
+            faiss_try(faiss_IndexFlat1D_update_permutation(self.inner))?;
+            Ok(())
+        }
+    }
+
+    /// Create a new flat index for 1-dimensional vectors with continuous
+    /// updates, holding a copy of the values in the given flat index.
+    ///
+    /// Faiss serializes Flat1D indexes as flat L2 indexes, so this is how
+    /// a Flat1D index is restored after reading it back.
+    ///
+    /// # Error
+    ///
+    /// Returns `Error::BadCast` if the flat index is not of dimensionality 1
+    /// or does not use the L2 metric.
+    pub fn from_flat(index: &flat::FlatIndexImpl) -> Result<Self> {
+        if index.d() != 1 || index.metric_type() != MetricType::L2 {
+            return Err(Error::BadCast);
+        }
+        let mut index_1d = Flat1DIndex::new(true)?;
+        index_1d.add(index.xb())?;
+        Ok(index_1d)
+    }
+
+    /// Obtain a reference to the indexed values, in insertion order.
+    pub fn xb(&self) -> &[f32] {
+        unsafe {
+            let mut xb = ptr::null_mut();
+            let mut len = 0;
+            faiss_IndexFlat_xb(self.inner, &mut xb, &mut len);
+            ::std::slice::from_raw_parts(xb, len)
+        }
+    }
+}
+
+impl IndexImpl {
+    /// Attempt a dynamic cast of an index to the Flat1D index type.
+    ///
+    /// Note that an index read back from a serialized Flat1D index is a
+    /// plain flat index, see [`Flat1DIndex::from_flat`] instead.
+    pub fn into_flat_1d(self) -> Result<Flat1DIndex> {
+        unsafe {
+            let new_inner = faiss_IndexFlat1D_cast(self.inner_ptr());
+            if new_inner.is_null() {
+                Err(Error::BadCast)
+            } else {
+                mem::forget(self);
+                Ok(Flat1DIndex { inner: new_inner })
+            }
+        }
+    }
+}
+
+impl NativeIndex for Flat1DIndex {
+    fn inner_ptr(&self) -> *mut FaissIndex {
+        self.inner
+    }
+}
+
+impl FromInnerPtr for Flat1DIndex {
+    unsafe fn from_inner_ptr(inner_ptr: *mut FaissIndex) -> Self {
+        Flat1DIndex {
+            inner: inner_ptr as *mut FaissIndexFlat1D,
+        }
+    }
+}
+
+impl TryFromInnerPtr for Flat1DIndex {
+    unsafe fn try_from_inner_ptr(inner_ptr: *mut FaissIndex) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        // safety: `inner_ptr` is documented to be a valid pointer to an index,
+        // so the dynamic cast should be safe.
+        #[allow(unused_unsafe)]
+        unsafe {
+            let new_inner = faiss_IndexFlat1D_cast(inner_ptr);
+            if new_inner.is_null() {
+                Err(Error::BadCast)
+            } else {
+                Ok(Flat1DIndex { inner: new_inner })
+            }
+        }
+    }
+}
+
+impl_native_index!(Flat1DIndex);
+
+impl_concurrent_index!(Flat1DIndex);
+
+#[cfg(test)]
+mod tests {
+    use super::Flat1DIndex;
+    use crate::index::flat::FlatIndexImpl;
+    use crate::index::io::{deserialize, serialize};
+    use crate::index::{ConcurrentIndex, Idx, Index, IndexKind, TryClone, UpcastIndex};
+
+    #[test]
+    fn flat_1d_search() {
+        let mut index = Flat1DIndex::new(true).unwrap();
+        assert_eq!(index.d(), 1);
+        index.add(&[5., 1., 3.]).unwrap();
+        assert_eq!(index.ntotal(), 3);
+        assert_eq!(index.xb(), &[5., 1., 3.]);
+
+        let result = ConcurrentIndex::search(&index, &[2.9, 6.], 2).unwrap();
+        assert_eq!(
+            result.labels,
+            vec![Idx::new(2), Idx::new(1), Idx::new(0), Idx::new(2)]
+        );
+    }
+
+    #[test]
+    fn flat_1d_update_permutation() {
+        let mut index = Flat1DIndex::new(false).unwrap();
+        index.add(&[5., 1., 3.]).unwrap();
+        assert!(ConcurrentIndex::search(&index, &[0.], 1).is_err());
+
+        index.update_permutation().unwrap();
+        let result = ConcurrentIndex::search(&index, &[0.], 1).unwrap();
+        assert_eq!(result.labels, vec![Idx::new(1)]);
+    }
+
+    #[test]
+    fn flat_1d_io() {
+        let mut index = Flat1DIndex::new(true).unwrap();
+        index.add(&[5., 1., 3.]).unwrap();
+        let bytes = serialize(&index).unwrap();
+
+        // the index is read back as a plain flat index
+        let index = deserialize(&bytes).unwrap();
+        assert_eq!(index.kind(), IndexKind::Flat);
+        let flat = index.into_flat().unwrap();
+        assert!(flat.try_clone().unwrap().upcast().into_flat_1d().is_err());
+
+        let index = Flat1DIndex::from_flat(&flat).unwrap();
+        assert_eq!(index.xb(), &[5., 1., 3.]);
+        let result = ConcurrentIndex::search(&index, &[4.2], 1).unwrap();
+        assert_eq!(result.labels, vec![Idx::new(0)]);
+
+        let index = index.upcast();
+        assert_eq!(index.kind(), IndexKind::Flat1D);
+        assert!(index.into_flat_1d().is_ok());
+
+        let flat = FlatIndexImpl::new_l2(2).unwrap();
+        assert!(Flat1DIndex::from_flat(&flat).is_err());
+        let flat = FlatIndexImpl::new_ip(1).unwrap();
+        assert!(Flat1DIndex::from_flat(&flat).is_err());
+    }
+}
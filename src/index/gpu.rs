@@ -349,6 +349,81 @@ where
             faiss_Index_set_verbose(self.inner, std::os::raw::c_int::from(value));
         }
     }
+
+    fn compute_residual(&mut self, x: &[f32], key: Idx) -> Result<Vec<f32>> {
+        let d = self.d() as usize;
+        if x.len() != d {
+            return Err(Error::BadLength);
+        }
+        unsafe {
+            let mut residual = vec![0_f32; d];
+            faiss_try(faiss_Index_compute_residual(
+                self.inner,
+                x.as_ptr(),
+                residual.as_mut_ptr(),
+                key.to_native(),
+            ))?;
+            Ok(residual)
+        }
+    }
+
+    fn compute_residual_n(&mut self, xs: &[f32], keys: &[Idx]) -> Result<Vec<f32>> {
+        let d = self.d() as usize;
+        if xs.len() != keys.len() * d {
+            return Err(Error::BadLength);
+        }
+        unsafe {
+            let mut residuals = vec![0_f32; xs.len()];
+            faiss_try(faiss_Index_compute_residual_n(
+                self.inner,
+                keys.len() as idx_t,
+                xs.as_ptr(),
+                residuals.as_mut_ptr(),
+                keys.as_ptr() as *const _,
+            ))?;
+            Ok(residuals)
+        }
+    }
+
+    fn reconstruct(&self, key: Idx) -> Result<Vec<f32>> {
+        unsafe {
+            let mut x = vec![0_f32; self.d() as usize];
+            faiss_try(faiss_Index_reconstruct(
+                self.inner,
+                key.to_native(),
+                x.as_mut_ptr(),
+            ))?;
+            Ok(x)
+        }
+    }
+
+    fn reconstruct_n(&self, start: Idx, n: usize) -> Result<Vec<f32>> {
+        unsafe {
+            let mut x = vec![0_f32; n * self.d() as usize];
+            faiss_try(faiss_Index_reconstruct_n(
+                self.inner,
+                start.to_native(),
+                n as idx_t,
+                x.as_mut_ptr(),
+            ))?;
+            Ok(x)
+        }
+    }
+
+    fn reconstruct_batch(&self, keys: &[Idx]) -> Result<Vec<f32>> {
+        unsafe {
+            let d = self.d() as usize;
+            let mut x = vec![0_f32; keys.len() * d];
+            for (i, key) in keys.iter().enumerate() {
+                faiss_try(faiss_Index_reconstruct(
+                    self.inner,
+                    key.to_native(),
+                    x.as_mut_ptr().add(i * d),
+                ))?;
+            }
+            Ok(x)
+        }
+    }
 }
 
 impl<'g, I> NativeIndex for GpuIndexImpl<'g, I>
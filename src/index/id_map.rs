@@ -54,17 +54,17 @@
 //!
 
 use crate::error::{Error, Result};
+use crate::faiss_try;
 use crate::index::{
     self, AssignSearchResult, ConcurrentIndex, CpuIndex, FromInnerPtr, Idx, Index, NativeIndex,
     RangeSearchResult, SearchResult,
 };
 use crate::selector::IdSelector;
-use crate::{faiss_try, MetricType};
 use faiss_sys::*;
 
+use std::collections::HashSet;
 use std::marker::PhantomData;
 use std::mem;
-use std::os::raw::c_int;
 use std::ptr;
 
 use super::IndexImpl;
@@ -195,194 +195,247 @@ where
     }
 }
 
-impl<I> Index for IdMap<I> {
-    fn is_trained(&self) -> bool {
-        unsafe { faiss_Index_is_trained(self.inner_ptr()) != 0 }
-    }
-
-    fn ntotal(&self) -> u64 {
-        unsafe { faiss_Index_ntotal(self.inner_ptr()) as u64 }
-    }
+impl_native_index!(<I> IdMap<I>);
 
-    fn d(&self) -> u32 {
-        unsafe { faiss_Index_d(self.inner_ptr()) as u32 }
-    }
-
-    fn metric_type(&self) -> MetricType {
-        unsafe { MetricType::from_code(faiss_Index_metric_type(self.inner_ptr()) as u32).unwrap() }
-    }
+impl_concurrent_index!(<I: ConcurrentIndex> IdMap<I>);
 
-    fn add(&mut self, x: &[f32]) -> Result<()> {
+impl IndexImpl {
+    /// Attempt a dynamic cast of the index to one that is [ID-mapped][1].
+    ///
+    /// [1]: crate::IdMap
+    pub fn into_id_map(self) -> Result<IdMap<IndexImpl>> {
         unsafe {
-            let n = x.len() / self.d() as usize;
-            faiss_try(faiss_Index_add(self.inner_ptr(), n as i64, x.as_ptr()))?;
-            Ok(())
+            let new_inner = faiss_IndexIDMap_cast(self.inner_ptr());
+            if new_inner.is_null() {
+                Err(Error::BadCast)
+            } else {
+                mem::forget(self);
+                let index_inner = faiss_IndexIDMap_sub_index(new_inner);
+                Ok(IdMap {
+                    inner: new_inner,
+                    index_inner,
+                    phantom: PhantomData,
+                })
+            }
         }
     }
+}
 
-    fn add_with_ids(&mut self, x: &[f32], xids: &[Idx]) -> Result<()> {
-        unsafe {
-            let n = x.len() / self.d() as usize;
-            faiss_try(faiss_Index_add_with_ids(
-                self.inner_ptr(),
-                n as i64,
-                x.as_ptr(),
-                xids.as_ptr() as *const _,
-            ))?;
-            Ok(())
-        }
-    }
-    fn train(&mut self, x: &[f32]) -> Result<()> {
-        unsafe {
-            let n = x.len() / self.d() as usize;
-            faiss_try(faiss_Index_train(self.inner_ptr(), n as i64, x.as_ptr()))?;
-            Ok(())
-        }
+/// Wrapper for implementing arbitrary ID mapping to an index,
+/// with a reverse mapping from IDs to positions in the index.
+///
+/// Unlike [`IdMap`], this wrapper can [reconstruct] vectors by their ID.
+/// It otherwise follows the same ownership model.
+///
+/// [`IdMap`]: struct.IdMap.html
+/// [reconstruct]: ../trait.Index.html#tymethod.reconstruct
+#[derive(Debug)]
+pub struct IdMap2<I> {
+    inner: *mut FaissIndexIDMap2,
+    index_inner: *mut FaissIndex,
+    phantom: PhantomData<I>,
+}
+
+unsafe impl<I: Send> Send for IdMap2<I> {}
+unsafe impl<I: Sync> Sync for IdMap2<I> {}
+impl<I: CpuIndex> CpuIndex for IdMap2<I> {}
+
+impl<I> NativeIndex for IdMap2<I> {
+    fn inner_ptr(&self) -> *mut FaissIndex {
+        self.inner
     }
-    fn assign(&mut self, query: &[f32], k: usize) -> Result<AssignSearchResult> {
+}
+
+impl<I> Drop for IdMap2<I> {
+    fn drop(&mut self) {
         unsafe {
-            let nq = query.len() / self.d() as usize;
-            let mut out_labels = vec![Idx::none(); k * nq];
-            faiss_try(faiss_Index_assign(
-                self.inner_ptr(),
-                nq as idx_t,
-                query.as_ptr(),
-                out_labels.as_mut_ptr() as *mut _,
-                k as i64,
-            ))?;
-            Ok(AssignSearchResult { labels: out_labels })
+            faiss_Index_free(self.inner);
         }
     }
-    fn search(&mut self, query: &[f32], k: usize) -> Result<SearchResult> {
+}
+
+impl<I> IdMap2<I>
+where
+    I: NativeIndex,
+{
+    /// Augment an index with arbitrary ID mapping.
+    pub fn new(index: I) -> Result<Self> {
         unsafe {
-            let nq = query.len() / self.d() as usize;
-            let mut distances = vec![0_f32; k * nq];
-            let mut labels = vec![Idx::none(); k * nq];
-            faiss_try(faiss_Index_search(
-                self.inner_ptr(),
-                nq as idx_t,
-                query.as_ptr(),
-                k as idx_t,
-                distances.as_mut_ptr(),
-                labels.as_mut_ptr() as *mut _,
-            ))?;
-            Ok(SearchResult { distances, labels })
+            let index_inner = index.inner_ptr();
+            let mut inner_ptr = ptr::null_mut();
+            faiss_try(faiss_IndexIDMap2_new(&mut inner_ptr, index_inner))?;
+            // let IDMap2 take ownership of the index
+            faiss_IndexIDMap2_set_own_fields(inner_ptr, 1);
+            mem::forget(index);
+
+            Ok(IdMap2 {
+                inner: inner_ptr,
+                index_inner,
+                phantom: PhantomData,
+            })
         }
     }
-    fn range_search(&mut self, query: &[f32], radius: f32) -> Result<RangeSearchResult> {
+
+    /// Retrieve a slice of the internal ID map.
+    pub fn id_map(&self) -> &[Idx] {
         unsafe {
-            let nq = (query.len() / self.d() as usize) as idx_t;
-            let mut p_res: *mut FaissRangeSearchResult = ::std::ptr::null_mut();
-            faiss_try(faiss_RangeSearchResult_new(&mut p_res, nq))?;
-            faiss_try(faiss_Index_range_search(
-                self.inner_ptr(),
-                nq,
-                query.as_ptr(),
-                radius,
-                p_res,
-            ))?;
-            Ok(RangeSearchResult { inner: p_res })
+            let mut id_ptr = ptr::null_mut();
+            let mut psize = 0;
+            faiss_IndexIDMap2_id_map(self.inner, &mut id_ptr, &mut psize);
+            ::std::slice::from_raw_parts(id_ptr as *const _, psize)
         }
     }
 
-    fn reset(&mut self) -> Result<()> {
+    /// Rebuild the reverse ID map from the internal ID map.
+    ///
+    /// The reverse map is kept up to date by all index operations,
+    /// so this is only needed if it could not be restored otherwise.
+    pub fn construct_rev_map(&mut self) -> Result<()> {
         unsafe {
-            faiss_try(faiss_Index_reset(self.inner_ptr()))?;
+            faiss_try(faiss_IndexIDMap2_construct_rev_map(self.inner))?;
             Ok(())
         }
     }
 
-    fn remove_ids(&mut self, sel: &IdSelector) -> Result<usize> {
-        unsafe {
-            let mut n_removed = 0;
-            faiss_try(faiss_Index_remove_ids(
-                self.inner_ptr(),
-                sel.inner_ptr(),
-                &mut n_removed,
-            ))?;
-            Ok(n_removed)
-        }
+    /// Insert the given vectors with the given IDs, replacing any vectors
+    /// already in the index with the same IDs.
+    ///
+    /// `x` must hold one vector per ID. The replaced vectors are
+    /// reconstructed beforehand, so the inner index must support
+    /// reconstruction.
+    ///
+    /// # Error
+    ///
+    /// Returns `Error::BadLength` if `x` does not hold one vector per ID,
+    /// or `Error::BadArgument` if the IDs are not unique,
+    /// in which case the index is left unchanged.
+    ///
+    /// If adding the new vectors fails, the replaced vectors are added back
+    /// and the original error is returned. Should that fail as well, the
+    /// replaced vectors are lost.
+    pub fn upsert(&mut self, ids: &[Idx], x: &[f32]) -> Result<()> {
+        self.upsert_with(ids, x, |index| index.add_with_ids(x, ids))
     }
 
-    fn verbose(&self) -> bool {
-        unsafe { faiss_Index_verbose(self.inner_ptr()) != 0 }
+    fn upsert_with<F>(&mut self, ids: &[Idx], x: &[f32], add: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self) -> Result<()>,
+    {
+        if x.len() != ids.len() * self.d() as usize {
+            return Err(Error::BadLength);
+        }
+        let mut new_ids = HashSet::with_capacity(ids.len());
+        if !ids.iter().all(|id| new_ids.insert(id.to_native())) {
+            return Err(Error::BadArgument);
+        }
+        let old_ids: Vec<Idx> = self
+            .id_map()
+            .iter()
+            .filter(|id| new_ids.contains(&id.to_native()))
+            .copied()
+            .collect();
+        let old_x = self.reconstruct_batch(&old_ids)?;
+        if !old_ids.is_empty() {
+            self.remove_ids(&IdSelector::batch(&old_ids)?)?;
+        }
+        if let Err(e) = add(self) {
+            // best effort, the original error matters more
+            let _ = self.add_with_ids(&old_x, &old_ids);
+            return Err(e);
+        }
+        Ok(())
     }
 
-    fn set_verbose(&mut self, value: bool) {
-        unsafe {
-            faiss_Index_set_verbose(self.inner_ptr(), c_int::from(value));
-        }
+    /// Obtain the raw pointer to the internal index.
+    ///
+    /// # Safety
+    ///
+    /// While this method is safe, note that the returned index pointer is
+    /// already owned by this ID map. Therefore, it is undefined behavior to
+    /// create a high-level index value from this pointer without first
+    /// decoupling this ownership. See [`into_inner`] for a safe alternative.
+    pub fn index_inner_ptr(&self) -> *mut FaissIndex {
+        self.index_inner
     }
-}
 
-impl<I> ConcurrentIndex for IdMap<I>
-where
-    I: ConcurrentIndex,
-{
-    fn assign(&self, query: &[f32], k: usize) -> Result<AssignSearchResult> {
+    /// Discard the ID map, recovering the index originally created without it.
+    pub fn into_inner(self) -> I
+    where
+        I: FromInnerPtr,
+    {
         unsafe {
-            let nq = query.len() / self.d() as usize;
-            let mut out_labels = vec![Idx::none(); k * nq];
-            faiss_try(faiss_Index_assign(
-                self.inner,
-                nq as idx_t,
-                query.as_ptr(),
-                out_labels.as_mut_ptr() as *mut _,
-                k as i64,
-            ))?;
-            Ok(AssignSearchResult { labels: out_labels })
+            // make id map disown the index
+            faiss_IndexIDMap2_set_own_fields(self.inner, 0);
+            // now it's safe to build a managed index
+            // (`index_inner` is expected to always point to a valid index)
+            I::from_inner_ptr(self.index_inner)
         }
     }
-    fn search(&self, query: &[f32], k: usize) -> Result<SearchResult> {
+
+    /// Discard the ID map, recovering the index originally created without it.
+    /// Safety build managed index from pointer.
+    pub fn try_into_inner(self) -> Result<I>
+    where
+        I: index::TryFromInnerPtr,
+    {
         unsafe {
-            let nq = query.len() / self.d() as usize;
-            let mut distances = vec![0_f32; k * nq];
-            let mut labels = vec![Idx::none(); k * nq];
-            faiss_try(faiss_Index_search(
-                self.inner,
-                nq as idx_t,
-                query.as_ptr(),
-                k as idx_t,
-                distances.as_mut_ptr(),
-                labels.as_mut_ptr() as *mut _,
-            ))?;
-            Ok(SearchResult { distances, labels })
+            // make id map disown the index
+            faiss_IndexIDMap2_set_own_fields(self.inner, 0);
+            // now it's safe to build a managed index
+            // (`index_inner` is expected to always point to a valid index)
+            I::try_from_inner_ptr(self.index_inner)
         }
     }
-    fn range_search(&self, query: &[f32], radius: f32) -> Result<RangeSearchResult> {
-        unsafe {
-            let nq = (query.len() / self.d() as usize) as idx_t;
-            let mut p_res: *mut FaissRangeSearchResult = ptr::null_mut();
-            faiss_try(faiss_RangeSearchResult_new(&mut p_res, nq))?;
-            faiss_try(faiss_Index_range_search(
-                self.inner,
-                nq,
-                query.as_ptr(),
-                radius,
-                p_res,
-            ))?;
-            Ok(RangeSearchResult { inner: p_res })
+
+    /// Specialization of the index type inside `IdMap2`.
+    pub fn try_cast_inner_index<B>(self) -> Result<IdMap2<B>>
+    where
+        B: index::TryFromInnerPtr,
+    {
+        // safety: index_inner is expected to always point to a valid index
+        let r = unsafe { B::try_from_inner_ptr(self.index_inner) };
+        if let Ok(index) = r {
+            let res = IdMap2 {
+                inner: self.inner,
+                index_inner: index.inner_ptr(),
+                phantom: PhantomData,
+            };
+            mem::forget(index);
+            mem::forget(self);
+
+            Ok(res)
+        } else {
+            Err(Error::BadCast)
         }
     }
 }
 
+impl_native_index!(<I> IdMap2<I>);
+
+impl_concurrent_index!(<I: ConcurrentIndex> IdMap2<I>);
+
 impl IndexImpl {
-    /// Attempt a dynamic cast of the index to one that is [ID-mapped][1].
+    /// Attempt a dynamic cast of the index to one that is ID-mapped
+    /// with a [reverse map][1].
     ///
-    /// [1]: crate::IdMap
-    pub fn into_id_map(self) -> Result<IdMap<IndexImpl>> {
+    /// The reverse map is rebuilt in the process.
+    ///
+    /// [1]: IdMap2
+    pub fn into_id_map2(self) -> Result<IdMap2<IndexImpl>> {
         unsafe {
-            let new_inner = faiss_IndexIDMap_cast(self.inner_ptr());
+            let new_inner = faiss_IndexIDMap2_cast(self.inner_ptr());
             if new_inner.is_null() {
                 Err(Error::BadCast)
             } else {
                 mem::forget(self);
-                let index_inner = faiss_IndexIDMap_sub_index(new_inner);
-                Ok(IdMap {
+                let index_inner = faiss_IndexIDMap2_sub_index(new_inner);
+                let mut index = IdMap2 {
                     inner: new_inner,
                     index_inner,
                     phantom: PhantomData,
-                })
+                };
+                index.construct_rev_map()?;
+                Ok(index)
             }
         }
     }
@@ -390,7 +443,8 @@ impl IndexImpl {
 
 #[cfg(test)]
 mod tests {
-    use super::IdMap;
+    use super::{IdMap, IdMap2};
+    use crate::error::Error;
     use crate::index::{flat::FlatIndexImpl, index_factory, Idx, Index, IndexImpl};
     use crate::selector::IdSelector;
     use crate::MetricType;
@@ -503,4 +557,84 @@ mod tests {
 
         assert_eq!(id_map.d(), 4);
     }
+
+    #[test]
+    fn id_map2_reconstruct() {
+        let index = FlatIndexImpl::new_l2(4).unwrap();
+        let mut index = IdMap2::new(index).unwrap();
+        index
+            .add_with_ids(
+                &[1., 1., 1., 1., 2., 2., 2., 2.],
+                &[Idx::new(7), Idx::new(3)],
+            )
+            .unwrap();
+        assert_eq!(index.id_map(), &[Idx::new(7), Idx::new(3)]);
+        assert_eq!(index.reconstruct(Idx::new(3)).unwrap(), vec![2.; 4]);
+        assert_eq!(index.reconstruct(Idx::new(7)).unwrap(), vec![1.; 4]);
+        assert!(index.reconstruct(Idx::new(1)).is_err());
+
+        let flat_index: FlatIndexImpl = index.into_inner();
+        assert_eq!(flat_index.ntotal(), 2);
+    }
+
+    #[test]
+    fn id_map2_upsert() {
+        let index = index_factory(4, "Flat", MetricType::L2).unwrap();
+        let mut index = IdMap2::new(index).unwrap();
+        index
+            .add_with_ids(
+                &[1., 1., 1., 1., 2., 2., 2., 2.],
+                &[Idx::new(7), Idx::new(3)],
+            )
+            .unwrap();
+
+        index
+            .upsert(
+                &[Idx::new(3), Idx::new(9)],
+                &[5., 5., 5., 5., 6., 6., 6., 6.],
+            )
+            .unwrap();
+        assert_eq!(index.ntotal(), 3);
+        assert_eq!(index.reconstruct(Idx::new(7)).unwrap(), vec![1.; 4]);
+        assert_eq!(index.reconstruct(Idx::new(3)).unwrap(), vec![5.; 4]);
+        assert_eq!(index.reconstruct(Idx::new(9)).unwrap(), vec![6.; 4]);
+
+        assert_eq!(
+            index.upsert(&[Idx::new(3)], &[0.; 3]),
+            Err(Error::BadLength)
+        );
+        assert_eq!(
+            index.upsert(&[Idx::new(9), Idx::new(9)], &[0.; 8]),
+            Err(Error::BadArgument)
+        );
+        assert_eq!(index.reconstruct(Idx::new(3)).unwrap(), vec![5.; 4]);
+        assert_eq!(index.reconstruct(Idx::new(9)).unwrap(), vec![6.; 4]);
+
+        // the replaced vectors are restored if adding the new ones fails
+        let r = index.upsert_with(&[Idx::new(3), Idx::new(4)], &[0.; 8], |_| {
+            Err(Error::BadCast)
+        });
+        assert_eq!(r, Err(Error::BadCast));
+        assert_eq!(index.ntotal(), 3);
+        assert_eq!(index.reconstruct(Idx::new(3)).unwrap(), vec![5.; 4]);
+        assert!(index.reconstruct(Idx::new(4)).is_err());
+
+        let index: IdMap2<FlatIndexImpl> = index.try_cast_inner_index().unwrap();
+        assert_eq!(index.ntotal(), 3);
+    }
+
+    #[test]
+    fn index_impl_to_id_map2() {
+        let mut index = index_factory(4, "IDMap2,Flat", MetricType::L2).unwrap();
+        index.add_with_ids(&[1.; 4], &[Idx::new(12)]).unwrap();
+        let bytes = crate::index::io::serialize(&index).unwrap();
+        let index = crate::index::io::deserialize(&bytes).unwrap();
+        assert!(index_factory(4, "IDMap,Flat", MetricType::L2)
+            .unwrap()
+            .into_id_map2()
+            .is_err());
+
+        let id_map = index.into_id_map2().unwrap();
+        assert_eq!(id_map.reconstruct(Idx::new(12)).unwrap(), vec![1.; 4]);
+    }
 }
@@ -2,68 +2,116 @@
 
 use crate::error::{Error, Result};
 use crate::faiss_try;
+use crate::index::io_stream;
 use crate::index::{CpuIndex, FromInnerPtr, IndexImpl, NativeIndex};
 use bytes::Bytes;
 use faiss_sys::*;
 use std::ffi::CString;
-use std::os::raw::c_int;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Write};
+use std::path::Path;
 use std::ptr;
-use std::ptr::null_mut;
-use std::sync::atomic::AtomicPtr;
 
+pub use super::io_envelope::{read_envelope, write_envelope, EnvelopeHeader};
 pub use super::io_flags::IoFlags;
+pub use super::io_inspect::{
+    IndexDetails, IndexInfo, InvertedListsInfo, IvfEncoding, IvfInfo, TransformInfo,
+};
 
 /// Write an index to a file.
 ///
 /// # Error
 ///
-/// This function returns an error if the description contains any byte with the value `\0` (since
+/// This function returns an error if the file path contains any byte with the value `\0` (since
 /// it cannot be converted to a C string), or if the internal index writing operation fails.
 pub fn write_index<I, P>(index: &I, file_name: P) -> Result<()>
 where
     I: NativeIndex,
     I: CpuIndex,
-    P: AsRef<str>,
+    P: AsRef<Path>,
 {
     unsafe {
-        let f = file_name.as_ref();
-        let f = CString::new(f).map_err(|_| Error::BadFilePath)?;
+        let f = path_to_cstring(file_name.as_ref())?;
 
         faiss_try(faiss_write_index_fname(index.inner_ptr(), f.as_ptr()))?;
         Ok(())
     }
 }
 
-pub unsafe fn serialize(index: &IndexImpl) -> Result<Vec<u8>> {
-    unsafe {
-        let mut size = 0;
-        let mut capacity = 0;
-        let mut bytes = null_mut();
-        faiss_try(serialize_index(
-            index.inner_ptr(),
-            &mut bytes,
-            &mut size,
-            &mut capacity,
-        ))?;
-        let bytes = std::slice::from_raw_parts(bytes, size); //TODO: free memory
-        let bytes = bytes.to_vec(); //TODO: avoid copy
-        Ok(bytes)
-    }
+/// Write an index to an arbitrary byte sink, in the same format as
+/// [`write_index`].
+///
+/// The index is streamed into `writer` as it is being written,
+/// without buffering the whole serialized index in memory.
+/// The writer is flushed once the index has been fully written.
+///
+/// # Error
+///
+/// This function returns an [`Error::Io`] if `writer` fails,
+/// or an error if the internal index writing operation fails.
+pub fn write_index_to<I, W>(index: &I, writer: W) -> Result<()>
+where
+    I: NativeIndex,
+    I: CpuIndex,
+    W: Write,
+{
+    io_stream::with_write_stream(writer, |f| unsafe {
+        faiss_try(faiss_write_index(index.inner_ptr(), f))?;
+        Ok(())
+    })
+}
+
+/// Serialize an index into a vector of bytes, in the same format as
+/// [`write_index`].
+///
+/// The index is written directly into the returned vector through
+/// [`write_index_to`], so the buffer is owned by Rust from the start and
+/// no intermediate native buffer is allocated.
+///
+/// # Error
+///
+/// This function returns an error if the internal index writing operation fails,
+/// or an [`Error::Io`] on platforms where [`write_index_to`] is unsupported.
+pub fn serialize<I>(index: &I) -> Result<Vec<u8>>
+where
+    I: NativeIndex,
+    I: CpuIndex,
+{
+    let mut buf = Vec::new();
+    write_index_to(index, &mut buf)?;
+    Ok(buf)
+}
+
+/// Serialize an index into a [`Bytes`] buffer, in the same format as
+/// [`write_index`].
+///
+/// The vector produced by [`serialize`] is handed over to the returned
+/// value without being copied.
+///
+/// # Error
+///
+/// This function returns an error if the internal index writing operation fails,
+/// or an [`Error::Io`] on platforms where [`write_index_to`] is unsupported.
+pub fn serialize_bytes<I>(index: &I) -> Result<Bytes>
+where
+    I: NativeIndex,
+    I: CpuIndex,
+{
+    serialize(index).map(Bytes::from)
 }
 
 /// Read an index from a file.
 ///
 /// # Error
 ///
-/// This function returns an error if the description contains any byte with the value `\0` (since
+/// This function returns an error if the file path contains any byte with the value `\0` (since
 /// it cannot be converted to a C string), or if the internal index reading operation fails.
 pub fn read_index<P>(file_name: P) -> Result<IndexImpl>
 where
-    P: AsRef<str>,
+    P: AsRef<Path>,
 {
     unsafe {
-        let f = file_name.as_ref();
-        let f = CString::new(f).map_err(|_| Error::BadFilePath)?;
+        let f = path_to_cstring(file_name.as_ref())?;
         let mut inner = ptr::null_mut();
         faiss_try(faiss_read_index_fname(
             f.as_ptr(),
@@ -74,12 +122,42 @@ where
     }
 }
 
+/// Read an index from an arbitrary byte source, as produced by
+/// [`write_index_to`] or [`write_index`].
+///
+/// Note that the reader may be consumed beyond the end of the index,
+/// since the native stream reads its input in blocks.
+///
+/// # Error
+///
+/// This function returns an [`Error::Io`] if `reader` fails,
+/// or an error if the internal index reading operation fails
+/// (including when the input ends prematurely).
+pub fn read_index_from<R>(reader: R) -> Result<IndexImpl>
+where
+    R: Read,
+{
+    io_stream::with_read_stream(reader, |f| unsafe {
+        let mut inner = ptr::null_mut();
+        faiss_try(faiss_read_index(
+            f,
+            IoFlags::MEM_RESIDENT.into(),
+            &mut inner,
+        ))?;
+        Ok(IndexImpl::from_inner_ptr(inner))
+    })
+}
+
+/// Deserialize an index from a slice of bytes, as produced by [`serialize`]
+/// or [`serialize_bytes`].
+///
+/// # Error
+///
+/// This function returns an error if the internal index reading operation fails.
 pub fn deserialize(bytes: &[u8]) -> Result<IndexImpl> {
     unsafe {
-        let size = bytes.len() as usize;
-        let bytes = bytes.as_ptr() as *const u8;
-        let mut inner = null_mut();
-        faiss_try(deserialize_index(bytes, size, &mut inner))?;
+        let mut inner = ptr::null_mut();
+        faiss_try(deserialize_index(bytes.as_ptr(), bytes.len(), &mut inner))?;
         Ok(IndexImpl::from_inner_ptr(inner))
     }
 }
@@ -90,30 +168,72 @@ pub fn deserialize(bytes: &[u8]) -> Result<IndexImpl> {
 ///
 /// # Error
 ///
-/// This function returns an error if the description contains any byte with the value `\0` (since
+/// This function returns an error if the file path contains any byte with the value `\0` (since
 /// it cannot be converted to a C string), or if the internal index reading operation fails.
 pub fn read_index_with_flags<P>(file_name: P, io_flags: IoFlags) -> Result<IndexImpl>
 where
-    P: AsRef<str>,
+    P: AsRef<Path>,
 {
     unsafe {
-        let f = file_name.as_ref();
-        let f = CString::new(f).map_err(|_| Error::BadFilePath)?;
+        let f = path_to_cstring(file_name.as_ref())?;
         let mut inner = ptr::null_mut();
         faiss_try(faiss_read_index_fname(
             f.as_ptr(),
-            io_flags.0 as c_int,
+            io_flags.bits(),
             &mut inner,
         ))?;
         Ok(IndexImpl::from_inner_ptr(inner))
     }
 }
 
+/// Describe the index stored in a file, without loading it.
+///
+/// Only the headers of the index and of its nested sub-indexes and vector
+/// transforms are read; the bulk of the data is skipped over.
+///
+/// # Error
+///
+/// This function returns an [`Error::Io`] if the file cannot be read, if it
+/// is truncated, or if it contains an index type which is not supported.
+pub fn inspect<P>(file_name: P) -> Result<IndexInfo>
+where
+    P: AsRef<Path>,
+{
+    let file = File::open(file_name)?;
+    super::io_inspect::parse(BufReader::new(file))
+}
+
+/// Describe the index stored in a slice of bytes, as produced by
+/// [`serialize`] or [`serialize_bytes`].
+///
+/// # Error
+///
+/// This function returns an [`Error::Io`] if the data is truncated,
+/// or if it contains an index type which is not supported.
+pub fn inspect_bytes(bytes: &[u8]) -> Result<IndexInfo> {
+    super::io_inspect::parse(Cursor::new(bytes))
+}
+
+/// Convert a file path into a C string, keeping its raw bytes on Unix
+/// so that non-UTF-8 paths are supported.
+fn path_to_cstring(path: &Path) -> Result<CString> {
+    #[cfg(unix)]
+    let bytes = {
+        use std::os::unix::ffi::OsStrExt;
+        path.as_os_str().as_bytes()
+    };
+    #[cfg(not(unix))]
+    let bytes = path.to_str().ok_or(Error::BadFilePath)?.as_bytes();
+    CString::new(bytes).map_err(|_| Error::BadFilePath)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::index::flat::FlatIndex;
-    use crate::index::{Index, UpcastIndex};
+    use crate::index::id_map::IdMap;
+    use crate::index::{index_factory, Idx, Index, UpcastIndex};
+    use crate::MetricType;
 
     const D: u32 = 8;
 
@@ -133,7 +253,7 @@ mod tests {
         let filepath = ::std::env::temp_dir().join("test_write_read.index");
         let filename = filepath.to_str().unwrap();
         write_index(&index, filename).unwrap();
-        let index = read_index(&filename).unwrap();
+        let index = read_index(filename).unwrap();
         assert_eq!(index.ntotal(), 5);
         ::std::fs::remove_file(&filepath).unwrap();
     }
@@ -156,6 +276,203 @@ mod tests {
         assert_eq!(index.ntotal(), 5);
     }
 
+    #[test]
+    fn serialize_bytes_deserialize() {
+        let mut index = FlatIndex::new_l2(D).unwrap();
+        let some_data = &[
+            7.5_f32, -7.5, 7.5, -7.5, 7.5, 7.5, 7.5, 7.5, -1., 1., 1., 1., 1., 1., 1., -1., 4.,
+            -4., -8., 1., 1., 2., 4., -1., 8., 8., 10., -10., -10., 10., -10., 10., 16., 16., 32.,
+            25., 20., 20., 40., 15.,
+        ];
+        index.add(some_data).unwrap();
+
+        let bytes = serialize_bytes(&index).unwrap();
+        assert_eq!(&bytes[..], &serialize(&index).unwrap()[..]);
+        let cloned = bytes.clone();
+        drop(bytes);
+
+        let index = deserialize(&cloned).unwrap();
+        assert_eq!(index.ntotal(), 5);
+        assert_eq!(index.d(), D);
+    }
+
+    #[test]
+    fn write_to_read_from() {
+        let mut index = FlatIndex::new_l2(D).unwrap();
+        let some_data = &[
+            7.5_f32, -7.5, 7.5, -7.5, 7.5, 7.5, 7.5, 7.5, -1., 1., 1., 1., 1., 1., 1., -1., 4.,
+            -4., -8., 1., 1., 2., 4., -1., 8., 8., 10., -10., -10., 10., -10., 10., 16., 16., 32.,
+            25., 20., 20., 40., 15.,
+        ];
+        index.add(some_data).unwrap();
+
+        let mut buf = Vec::new();
+        write_index_to(&index, &mut buf).unwrap();
+        assert_eq!(buf, serialize(&index).unwrap());
+
+        let mut index = read_index_from(&buf[..]).unwrap();
+        assert_eq!(index.ntotal(), 5);
+        assert_eq!(index.d(), D);
+        let result = index.search(&some_data[8..16], 1).unwrap();
+        assert_eq!(result.labels, vec![Idx::new(1)]);
+    }
+
+    #[test]
+    fn write_to_failing_writer() {
+        struct FailingWriter;
+
+        impl Write for FailingWriter {
+            fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::new(std::io::ErrorKind::Other, "disk full"))
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut index = FlatIndex::new_l2(D).unwrap();
+        index.add(&[1.; D as usize * 1024]).unwrap();
+        match write_index_to(&index, FailingWriter) {
+            Err(Error::Io(e)) => assert_eq!(e.to_string(), "disk full"),
+            r => panic!("unexpected result: {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn read_from_truncated() {
+        let mut index = FlatIndex::new_l2(D).unwrap();
+        index.add(&[1.; D as usize * 16]).unwrap();
+        let bytes = serialize(&index).unwrap();
+        assert!(read_index_from(&bytes[..bytes.len() / 2]).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_read_non_utf8_path() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut index = FlatIndex::new_l2(D).unwrap();
+        index.add(&[1.; D as usize * 3]).unwrap();
+
+        let filepath =
+            ::std::env::temp_dir().join(OsStr::from_bytes(b"test_write_read_\xff.index"));
+        write_index(&index, &filepath).unwrap();
+        let index = read_index_with_flags(&filepath, IoFlags::READ_ONLY).unwrap();
+        assert_eq!(index.ntotal(), 3);
+        ::std::fs::remove_file(&filepath).unwrap();
+    }
+
+    #[test]
+    fn bad_file_path() {
+        let index = FlatIndex::new_l2(D).unwrap();
+        assert_eq!(write_index(&index, "a\0b"), Err(Error::BadFilePath));
+        assert!(matches!(read_index("a\0b"), Err(Error::BadFilePath)));
+    }
+
+    #[test]
+    fn inspect_flat() {
+        let mut index = FlatIndex::new_ip(D).unwrap();
+        index.add(&[1.; D as usize * 5]).unwrap();
+
+        let filepath = ::std::env::temp_dir().join("test_inspect_flat.index");
+        write_index(&index, &filepath).unwrap();
+        let info = inspect(&filepath).unwrap();
+        ::std::fs::remove_file(&filepath).unwrap();
+
+        assert_eq!(info.fourcc, "IxFI");
+        assert_eq!(info.d, D);
+        assert_eq!(info.ntotal, 5);
+        assert_eq!(info.metric(), Some(MetricType::InnerProduct));
+        assert!(info.is_trained);
+        assert_eq!(info.code_size, Some(D as usize * 4));
+        assert_eq!(info.details, IndexDetails::Flat);
+        assert_eq!(info, inspect_bytes(&serialize(&index).unwrap()).unwrap());
+    }
+
+    #[test]
+    fn inspect_nested() {
+        let data: Vec<f32> = (0..D as usize * 64).map(|i| (i % 13) as f32).collect();
+        let mut index = index_factory(D, "PCA4,IVF2,Flat", MetricType::L2).unwrap();
+        index.train(&data).unwrap();
+        let mut index = IdMap::new(index).unwrap();
+        let ids: Vec<_> = (0..64).map(|i| Idx::new(i * 10)).collect();
+        index.add_with_ids(&data, &ids).unwrap();
+
+        let info = inspect_bytes(&serialize(&index).unwrap()).unwrap();
+        assert_eq!(info.fourcc, "IxMp");
+        assert_eq!(info.ntotal, 64);
+        let pretransform = match &info.details {
+            IndexDetails::IdMap { index } => index,
+            details => panic!("unexpected details: {:?}", details),
+        };
+        assert_eq!(pretransform.fourcc, "IxPT");
+        let (chain, ivf) = match &pretransform.details {
+            IndexDetails::PreTransform { chain, index } => (chain, index),
+            details => panic!("unexpected details: {:?}", details),
+        };
+        assert_eq!(chain.len(), 1);
+        assert_eq!((chain[0].d_in, chain[0].d_out), (D as i32, 4));
+        assert!(chain[0].is_trained);
+
+        assert_eq!(ivf.d, 4);
+        assert_eq!(ivf.code_size, Some(16));
+        let ivf_info = match &ivf.details {
+            IndexDetails::Ivf(ivf_info) => ivf_info,
+            details => panic!("unexpected details: {:?}", details),
+        };
+        assert_eq!(ivf_info.nlist, 2);
+        assert_eq!(ivf_info.encoding, IvfEncoding::Flat);
+        assert_eq!(ivf_info.quantizer.ntotal, 2);
+        assert_eq!(ivf.sub_indexes(), vec![&*ivf_info.quantizer]);
+        let invlists = ivf_info.invlists.as_ref().unwrap();
+        assert_eq!(invlists.list_sizes.iter().sum::<usize>(), 64);
+    }
+
+    #[test]
+    fn inspect_invalid() {
+        let mut index = FlatIndex::new_l2(D).unwrap();
+        index.add(&[1.; D as usize * 5]).unwrap();
+        let bytes = serialize(&index).unwrap();
+
+        match inspect_bytes(&bytes[..bytes.len() - 1]) {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match inspect_bytes(b"XXXX") {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidData),
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        // ID maps nested over and over
+        let mut header = b"IxMp".to_vec();
+        header.extend_from_slice(&D.to_le_bytes());
+        header.extend_from_slice(&[0; 24]);
+        header.push(1);
+        header.extend_from_slice(&1_u32.to_le_bytes());
+        match inspect_bytes(&header.repeat(100_000)) {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidData),
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        // pre-transforms with nested ITQ transforms
+        let mut header = b"IxPT".to_vec();
+        header.extend_from_slice(&D.to_le_bytes());
+        header.extend_from_slice(&[0; 24]);
+        header.push(1);
+        header.extend_from_slice(&1_u32.to_le_bytes());
+        header.extend_from_slice(&1_i32.to_le_bytes());
+        let mut itq = b"Viqt".to_vec();
+        itq.extend_from_slice(&0_u64.to_le_bytes());
+        itq.push(0);
+        header.extend(itq.repeat(100_000));
+        match inspect_bytes(&header) {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidData),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
     #[test]
     fn test_read_with_flags() {
         let index = read_index_with_flags("file_name", IoFlags::MEM_MAP | IoFlags::READ_ONLY);
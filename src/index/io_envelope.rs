@@ -0,0 +1,382 @@
+//! Checksummed container format around serialized indexes.
+//!
+//! An envelope consists of a header followed by the index payload, in the
+//! same format as [`serialize`](crate::index::io::serialize). All integers
+//! are little endian, and strings are prefixed by their length as a `u32`.
+//!
+//! | field            | type                    |
+//! |------------------|-------------------------|
+//! | magic            | `b"FAISSENV"`           |
+//! | format version   | `u32`                   |
+//! | crate version    | string                  |
+//! | bindings version | string                  |
+//! | index type       | 4 bytes                 |
+//! | dimension        | `u32`                   |
+//! | metric type      | `u32`                   |
+//! | `ntotal`         | `u64`                   |
+//! | metadata         | `u32` count, then pairs of strings |
+//! | payload length   | `u64`                   |
+//! | payload CRC-32   | `u32`                   |
+//! | header CRC-32    | `u32`, of all fields above |
+//! | payload          | bytes                   |
+
+use crate::error::{Error, Result};
+use crate::index::io::{deserialize, serialize_bytes};
+use crate::index::{CpuIndex, IndexImpl, NativeIndex};
+use crate::metric::MetricType;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+const MAGIC: &[u8; 8] = b"FAISSENV";
+const FORMAT_VERSION: u32 = 1;
+
+/// The header of an index envelope.
+///
+/// See [`write_envelope`] and [`read_envelope`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvelopeHeader {
+    /// The version of the envelope format
+    pub format_version: u32,
+    /// The version of this crate which wrote the envelope
+    pub crate_version: String,
+    /// The version of the `faiss-sys` bindings which wrote the envelope
+    ///
+    /// The Faiss C API does not report the version of the native library,
+    /// so this is the closest available indication of it.
+    pub bindings_version: String,
+    /// The four-character code identifying the index type (e.g. `"IxF2"`)
+    pub fourcc: String,
+    /// The dimensionality of the indexed vectors
+    pub d: u32,
+    /// The metric type of the index
+    pub metric_type: MetricType,
+    /// The number of indexed vectors
+    pub ntotal: u64,
+    /// Arbitrary user metadata
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// Write an index to `writer`, wrapped in a checksummed envelope
+/// carrying the given user metadata.
+///
+/// # Error
+///
+/// This function returns an [`Error::Io`] if `writer` fails,
+/// or an error if the internal index writing operation fails.
+pub fn write_envelope<I, W>(
+    index: &I,
+    metadata: &BTreeMap<String, String>,
+    mut writer: W,
+) -> Result<()>
+where
+    I: NativeIndex,
+    I: CpuIndex,
+    W: Write,
+{
+    let payload = serialize_bytes(index)?;
+    let mut header = Vec::new();
+    header.extend_from_slice(MAGIC);
+    put_u32(&mut header, FORMAT_VERSION);
+    put_str(&mut header, env!("CARGO_PKG_VERSION"))?;
+    put_str(&mut header, faiss_sys::BINDINGS_VERSION)?;
+    let mut fourcc = [0; 4];
+    let n = payload.len().min(4);
+    fourcc[..n].copy_from_slice(&payload[..n]);
+    header.extend_from_slice(&fourcc);
+    put_u32(&mut header, index.d());
+    put_u32(&mut header, index.metric_type().code());
+    header.extend_from_slice(&index.ntotal().to_le_bytes());
+    put_u32(&mut header, len_u32(metadata.len())?);
+    for (key, value) in metadata {
+        put_str(&mut header, key)?;
+        put_str(&mut header, value)?;
+    }
+    header.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    put_u32(&mut header, crc32(&payload));
+    let header_crc = crc32(&header);
+    put_u32(&mut header, header_crc);
+
+    writer.write_all(&header)?;
+    writer.write_all(&payload)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read an index wrapped in an envelope, as produced by [`write_envelope`].
+///
+/// Both the header and the payload are verified
+/// before the index is deserialized.
+///
+/// # Error
+///
+/// This function returns [`Error::Corrupt`] if the envelope is truncated,
+/// fails verification, has an unsupported format version or an unknown
+/// metric type, an
+/// [`Error::Io`] if `reader` fails, or an error if the internal index
+/// reading operation fails.
+pub fn read_envelope<R>(reader: R) -> Result<(IndexImpl, EnvelopeHeader)>
+where
+    R: Read,
+{
+    let mut r = CrcReader {
+        inner: reader,
+        crc: Crc32::new(),
+    };
+
+    let mut magic = [0; 8];
+    r.read_exact(&mut magic).map_err(truncated)?;
+    if &magic != MAGIC {
+        return Err(corrupt("not an index envelope"));
+    }
+    let format_version = r.u32()?;
+    if format_version != FORMAT_VERSION {
+        return Err(corrupt(format!(
+            "unsupported envelope format version {}",
+            format_version
+        )));
+    }
+    let crate_version = r.string()?;
+    let bindings_version = r.string()?;
+    let mut fourcc = [0; 4];
+    r.read_exact(&mut fourcc).map_err(truncated)?;
+    let fourcc = String::from_utf8_lossy(&fourcc).into_owned();
+    let d = r.u32()?;
+    let metric_type = r.u32()?;
+    let ntotal = r.u64()?;
+    let n = r.u32()?;
+    let mut metadata = BTreeMap::new();
+    for _ in 0..n {
+        let key = r.string()?;
+        let value = r.string()?;
+        metadata.insert(key, value);
+    }
+    let payload_len = r.u64()?;
+    let payload_crc = r.u32()?;
+    let header_crc = r.crc.finish();
+    if r.u32()? != header_crc {
+        return Err(corrupt("header checksum mismatch"));
+    }
+    let metric_type = MetricType::from_code(metric_type)
+        .ok_or_else(|| corrupt(format!("unknown metric type {}", metric_type)))?;
+
+    let mut payload = Vec::new();
+    r.inner
+        .by_ref()
+        .take(payload_len)
+        .read_to_end(&mut payload)?;
+    if (payload.len() as u64) < payload_len {
+        return Err(corrupt("truncated envelope"));
+    }
+    if crc32(&payload) != payload_crc {
+        return Err(corrupt("payload checksum mismatch"));
+    }
+
+    let index = deserialize(&payload)?;
+    let header = EnvelopeHeader {
+        format_version,
+        crate_version,
+        bindings_version,
+        fourcc,
+        d,
+        metric_type,
+        ntotal,
+        metadata,
+    };
+    Ok((index, header))
+}
+
+fn corrupt(msg: impl Into<String>) -> Error {
+    Error::Corrupt(msg.into())
+}
+
+fn truncated(e: io::Error) -> Error {
+    if e.kind() == io::ErrorKind::UnexpectedEof {
+        corrupt("truncated envelope")
+    } else {
+        Error::from(e)
+    }
+}
+
+fn len_u32(len: usize) -> Result<u32> {
+    u32::try_from(len).map_err(|_| {
+        Error::from(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "envelope field too large",
+        ))
+    })
+}
+
+fn put_u32(buf: &mut Vec<u8>, v: u32) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) -> Result<()> {
+    put_u32(buf, len_u32(s.len())?);
+    buf.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+/// A reader which keeps a running checksum of the header bytes.
+struct CrcReader<R> {
+    inner: R,
+    crc: Crc32,
+}
+
+impl<R: Read> CrcReader<R> {
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.inner.read_exact(buf)?;
+        self.crc.update(buf);
+        Ok(())
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let mut buf = [0; 4];
+        self.read_exact(&mut buf).map_err(truncated)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        let mut buf = [0; 8];
+        self.read_exact(&mut buf).map_err(truncated)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.u32()?;
+        // grow incrementally so that a corrupted length
+        // does not trigger a huge allocation
+        let mut buf = Vec::new();
+        self.inner
+            .by_ref()
+            .take(u64::from(len))
+            .read_to_end(&mut buf)?;
+        if buf.len() < len as usize {
+            return Err(corrupt("truncated envelope"));
+        }
+        self.crc.update(&buf);
+        String::from_utf8(buf).map_err(|_| corrupt("invalid string in envelope header"))
+    }
+}
+
+/// CRC-32 (IEEE 802.3) checksum.
+struct Crc32(u32);
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+impl Crc32 {
+    fn new() -> Self {
+        Crc32(!0)
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for &b in data {
+            self.0 = CRC32_TABLE[((self.0 ^ u32::from(b)) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::flat::FlatIndex;
+    use crate::index::io::serialize;
+    use crate::index::Index;
+
+    const D: u32 = 8;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn envelope_round_trip() {
+        let mut index = FlatIndex::new_l2(D).unwrap();
+        index.add(&[1.; D as usize * 5]).unwrap();
+        let mut metadata = BTreeMap::new();
+        metadata.insert("model".to_string(), "v2".to_string());
+
+        let mut buf = Vec::new();
+        write_envelope(&index, &metadata, &mut buf).unwrap();
+        let (index, header) = read_envelope(&buf[..]).unwrap();
+        assert_eq!(index.ntotal(), 5);
+        assert_eq!(index.d(), D);
+        assert_eq!(header.format_version, FORMAT_VERSION);
+        assert_eq!(header.crate_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(header.fourcc, "IxF2");
+        assert_eq!(header.d, D);
+        assert_eq!(header.metric_type, MetricType::L2);
+        assert_eq!(header.ntotal, 5);
+        assert_eq!(header.metadata, metadata);
+    }
+
+    #[test]
+    fn envelope_corrupt() {
+        let mut index = FlatIndex::new_l2(D).unwrap();
+        index.add(&[1.; D as usize * 5]).unwrap();
+        let mut buf = Vec::new();
+        write_envelope(&index, &BTreeMap::new(), &mut buf).unwrap();
+
+        let is_corrupt =
+            |r: Result<(IndexImpl, EnvelopeHeader)>| matches!(r, Err(Error::Corrupt(_)));
+        assert!(is_corrupt(read_envelope(&buf[..buf.len() - 1])));
+        assert!(is_corrupt(read_envelope(&buf[..20])));
+        assert!(is_corrupt(read_envelope(&b"not an envelope"[..])));
+
+        let mut flipped = buf.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert!(is_corrupt(read_envelope(&flipped[..])));
+        let mut flipped = buf.clone();
+        flipped[MAGIC.len() + 4] ^= 1;
+        assert!(is_corrupt(read_envelope(&flipped[..])));
+
+        // an unknown metric type, with a valid header checksum
+        let mut unknown = buf;
+        let metric_at = MAGIC.len()
+            + 4
+            + 4
+            + env!("CARGO_PKG_VERSION").len()
+            + 4
+            + faiss_sys::BINDINGS_VERSION.len()
+            + 4
+            + 4;
+        unknown[metric_at..metric_at + 4].copy_from_slice(&7_u32.to_le_bytes());
+        let crc_at = unknown.len() - serialize(&index).unwrap().len() - 4;
+        let header_crc = crc32(&unknown[..crc_at]);
+        unknown[crc_at..crc_at + 4].copy_from_slice(&header_crc.to_le_bytes());
+        match read_envelope(&unknown[..]) {
+            Err(Error::Corrupt(msg)) => assert!(msg.contains("metric type"), "{}", msg),
+            r => panic!("unexpected result {:?}", r.map(|(_, header)| header)),
+        }
+    }
+}
@@ -1,20 +1,57 @@
 //! Module containing the I/O flags.
 
+use crate::error::{Error, Result};
+use std::convert::TryFrom;
+
 /// I/O flags used during index reading.
 ///
+/// Flags can be combined with the `|` operator.
 /// Note that not all flags are applicable to all index types.
 #[derive(Debug, Copy, Clone, Eq, Hash, PartialEq)]
-pub struct IoFlags(pub(crate) u16);
+pub struct IoFlags(pub(crate) u32);
 
 impl IoFlags {
     /// Load entire index into memory (default behavior)
     pub const MEM_RESIDENT: Self = IoFlags(0x00);
     /// Memory-map index
+    ///
+    /// This is `FAISS_IO_FLAG_MMAP` from the C API, which Faiss handles
+    /// with the same bit as [`SKIP_STORAGE`](IoFlags::SKIP_STORAGE).
     pub const MEM_MAP: Self = IoFlags(0x01);
+    /// Skip the vector storage of graph-based indexes
+    pub const SKIP_STORAGE: Self = IoFlags(0x01);
     /// Index is read-only
     pub const READ_ONLY: Self = IoFlags(0x02);
+    /// Look for on-disk inverted lists in the same directory as the index
+    /// file, rather than at the path recorded in the index
+    pub const ONDISK_SAME_DIR: Self = IoFlags(0x04);
+    /// Do not load the inverted list data of IVF indexes
+    pub const SKIP_IVF_DATA: Self = IoFlags(0x08);
+    /// Do not load the precomputed tables of IVFPQ indexes
+    pub const SKIP_PRECOMPUTE_TABLE: Self = IoFlags(0x10);
+    /// Do not compute the symmetric distance tables of PQ indexes
+    pub const PQ_SKIP_SDC_TABLE: Self = IoFlags(0x20);
+    /// Memory-map the inverted lists of IVF indexes, loading them
+    /// as on-disk inverted lists
+    pub const MMAP_IVF: Self = IoFlags(MMAP_MAGIC | 0x08);
+
+    /// Retrieve the raw value of the flags, as passed to Faiss.
+    pub fn bits(self) -> i32 {
+        self.0 as i32
+    }
+
+    /// Check whether all flags in `other` are also set in `self`.
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
 }
 
+/// The high bits which Faiss uses to tag the IVF memory-mapping flag.
+const MMAP_MAGIC: u32 = 0x646f_0000;
+
+/// All single-bit flags known to Faiss.
+const KNOWN_BITS: u32 = 0x3f;
+
 impl Default for IoFlags {
     fn default() -> Self {
         IoFlags::MEM_RESIDENT
@@ -29,9 +66,22 @@ impl std::ops::BitOr for IoFlags {
     }
 }
 
-impl From<i32> for IoFlags {
-    fn from(n: i32) -> IoFlags {
-        IoFlags(n as u16)
+impl TryFrom<i32> for IoFlags {
+    type Error = Error;
+
+    /// Convert a raw flag value into I/O flags.
+    ///
+    /// # Error
+    ///
+    /// Returns [`Error::BadIoFlags`] if the value contains bits which are
+    /// not known to Faiss.
+    fn try_from(n: i32) -> Result<IoFlags> {
+        let n = n as u32;
+        let high = n & !KNOWN_BITS;
+        if high != 0 && (high != MMAP_MAGIC || n & IoFlags::SKIP_IVF_DATA.0 == 0) {
+            return Err(Error::BadIoFlags);
+        }
+        Ok(IoFlags(n))
     }
 }
 
@@ -56,5 +106,30 @@ mod tests {
     fn can_coerce_to_i32() {
         let mmap = IoFlags::MEM_MAP;
         assert_eq!(1, mmap.into());
+        assert_eq!(0x646f_0008, IoFlags::MMAP_IVF.bits());
+    }
+
+    #[test]
+    fn can_contain() {
+        let flags = IoFlags::MMAP_IVF | IoFlags::READ_ONLY;
+        assert!(flags.contains(IoFlags::SKIP_IVF_DATA));
+        assert!(flags.contains(IoFlags::READ_ONLY));
+        assert!(!flags.contains(IoFlags::ONDISK_SAME_DIR));
+    }
+
+    #[test]
+    fn try_from_i32() {
+        assert_eq!(
+            IoFlags::try_from(0x03),
+            Ok(IoFlags::MEM_MAP | IoFlags::READ_ONLY)
+        );
+        assert_eq!(
+            IoFlags::try_from(0x646f_000a),
+            Ok(IoFlags::MMAP_IVF | IoFlags::READ_ONLY)
+        );
+        assert_eq!(IoFlags::try_from(0x40), Err(Error::BadIoFlags));
+        assert_eq!(IoFlags::try_from(-1), Err(Error::BadIoFlags));
+        // the memory-mapping tag is only valid together with SKIP_IVF_DATA
+        assert_eq!(IoFlags::try_from(0x646f_0000), Err(Error::BadIoFlags));
     }
 }
@@ -0,0 +1,688 @@
+//! Parsing of serialized index headers.
+//!
+//! Faiss stores an index as a chain of sections, each starting with a
+//! four-character code which identifies the type of the section. This module
+//! walks that chain, collecting the header fields of every index and vector
+//! transform found along the way, while skipping over the bulk data (vectors,
+//! codes and IDs) without reading it into memory.
+
+use crate::error::Result;
+use crate::metric::MetricType;
+use std::convert::TryFrom;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Structured description of a serialized index.
+///
+/// See [`inspect`](crate::index::io::inspect) and
+/// [`inspect_bytes`](crate::index::io::inspect_bytes).
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexInfo {
+    /// The four-character code identifying the index type (e.g. `"IxF2"`)
+    pub fourcc: String,
+    /// The dimensionality of the indexed vectors
+    pub d: u32,
+    /// The number of indexed vectors
+    pub ntotal: u64,
+    /// The native code of the metric type
+    pub metric_type: u32,
+    /// The metric argument, only stored for metrics other than
+    /// inner product and L2
+    pub metric_arg: Option<f32>,
+    /// Whether the index is trained
+    pub is_trained: bool,
+    /// The size in bytes of each encoded vector,
+    /// if the index stores encoded vectors itself
+    pub code_size: Option<usize>,
+    /// Properties specific to the index type
+    pub details: IndexDetails,
+}
+
+impl IndexInfo {
+    /// Obtain the metric type of the index, if it is known to this crate.
+    pub fn metric(&self) -> Option<MetricType> {
+        MetricType::from_code(self.metric_type)
+    }
+
+    /// Obtain the indexes directly nested in this one.
+    pub fn sub_indexes(&self) -> Vec<&IndexInfo> {
+        match &self.details {
+            IndexDetails::Ivf(ivf) => vec![&ivf.quantizer],
+            IndexDetails::IdMap { index }
+            | IndexDetails::IdMap2 { index }
+            | IndexDetails::PreTransform { index, .. } => vec![index],
+            IndexDetails::Refine { base, refine, .. } => vec![base, refine],
+            IndexDetails::Hnsw { storage, .. } => vec![storage],
+            _ => vec![],
+        }
+    }
+}
+
+/// Properties specific to each type of serialized index.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IndexDetails {
+    /// Flat index, storing the full vectors
+    Flat,
+    /// Locality-sensitive hashing index
+    Lsh {
+        /// the number of hash bits per vector
+        nbits: i32,
+        /// whether the data is randomly rotated before hashing
+        rotate_data: bool,
+        /// whether the thresholds were trained
+        train_thresholds: bool,
+    },
+    /// Scalar quantizer index
+    ScalarQuantizer {
+        /// the native code of the quantizer type
+        qtype: u32,
+    },
+    /// Product quantizer index
+    ProductQuantizer {
+        /// the number of sub-quantizers
+        m: usize,
+        /// the number of bits per sub-quantizer index
+        nbits: usize,
+    },
+    /// Inverted file index
+    Ivf(IvfInfo),
+    /// ID map wrapper
+    IdMap {
+        /// the wrapped index
+        index: Box<IndexInfo>,
+    },
+    /// ID map wrapper with reverse mapping
+    IdMap2 {
+        /// the wrapped index
+        index: Box<IndexInfo>,
+    },
+    /// Index with a chain of vector transforms
+    PreTransform {
+        /// the transforms, in the order in which they are applied
+        chain: Vec<TransformInfo>,
+        /// the index receiving the transformed vectors
+        index: Box<IndexInfo>,
+    },
+    /// Index refining the results of a base index
+    Refine {
+        /// the base index
+        base: Box<IndexInfo>,
+        /// the index used for refinement
+        refine: Box<IndexInfo>,
+        /// the factor of results to retrieve from the base index
+        k_factor: f32,
+    },
+    /// Hierarchical navigable small world graph index
+    Hnsw {
+        /// the maximum level of the graph
+        max_level: i32,
+        /// the size of the candidate list used during construction
+        ef_construction: i32,
+        /// the size of the candidate list used during search
+        ef_search: i32,
+        /// the index storing the vectors
+        storage: Box<IndexInfo>,
+    },
+}
+
+/// Description of a serialized inverted file index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IvfInfo {
+    /// The number of inverted lists
+    pub nlist: usize,
+    /// The default number of lists visited during search
+    pub nprobe: usize,
+    /// The coarse quantizer
+    pub quantizer: Box<IndexInfo>,
+    /// The encoding of the vectors in the inverted lists
+    pub encoding: IvfEncoding,
+    /// Whether the residuals to the centroids are encoded,
+    /// if recorded for this index type
+    pub by_residual: Option<bool>,
+    /// The inverted lists, or `None` if they were not stored
+    pub invlists: Option<InvertedListsInfo>,
+}
+
+/// The encoding of the vectors in an inverted file index.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IvfEncoding {
+    /// Full vectors
+    Flat,
+    /// Scalar quantizer codes
+    ScalarQuantizer {
+        /// the native code of the quantizer type
+        qtype: u32,
+    },
+    /// Product quantizer codes
+    ProductQuantizer {
+        /// the number of sub-quantizers
+        m: usize,
+        /// the number of bits per sub-quantizer index
+        nbits: usize,
+    },
+}
+
+/// Description of serialized inverted lists.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvertedListsInfo {
+    /// The four-character code identifying the inverted lists type
+    pub fourcc: String,
+    /// The number of lists
+    pub nlist: usize,
+    /// The size in bytes of each code
+    pub code_size: usize,
+    /// The number of entries in each list
+    pub list_sizes: Vec<usize>,
+}
+
+/// Description of a serialized vector transform.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransformInfo {
+    /// The four-character code identifying the transform type (e.g. `"PcAm"`)
+    pub fourcc: String,
+    /// The input dimensionality
+    pub d_in: i32,
+    /// The output dimensionality
+    pub d_out: i32,
+    /// Whether the transform is trained
+    pub is_trained: bool,
+}
+
+/// Parse the description of the index stored in `reader`.
+pub(crate) fn parse<R: Read + Seek>(reader: R) -> Result<IndexInfo> {
+    let mut p = Parser {
+        r: reader,
+        depth: 0,
+    };
+    let info = p.index()?;
+    // skipping past the end of the input does not fail,
+    // so check that all skipped data was actually there
+    let pos = p.r.stream_position()?;
+    let end = p.r.seek(SeekFrom::End(0))?;
+    if pos > end {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(info)
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+const DIRECT_MAP_HASHTABLE: u8 = 2;
+
+/// Pad `v` with zeros up to length `len`,
+/// failing instead of aborting if the memory cannot be allocated.
+fn zero_extend(v: &mut Vec<usize>, len: usize) -> io::Result<()> {
+    v.try_reserve_exact(len - v.len())
+        .map_err(|_| invalid_data(format!("cannot allocate {} list sizes", len)))?;
+    v.resize(len, 0);
+    Ok(())
+}
+
+/// The maximum nesting depth of sub-indexes and vector transforms,
+/// so that crafted input cannot overflow the stack.
+const MAX_DEPTH: usize = 64;
+
+struct Parser<R> {
+    r: R,
+    /// the number of indexes and transforms currently being parsed
+    depth: usize,
+}
+
+impl<R: Read + Seek> Parser<R> {
+    fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut buf = [0; N];
+        self.r.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn bool(&mut self) -> io::Result<bool> {
+        Ok(self.array::<1>()?[0] != 0)
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        self.array().map(i32::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn i64(&mut self) -> io::Result<i64> {
+        self.array().map(i64::from_le_bytes)
+    }
+
+    fn size(&mut self) -> io::Result<usize> {
+        let n = u64::from_le_bytes(self.array()?);
+        usize::try_from(n).map_err(|_| invalid_data(format!("size {} out of range", n)))
+    }
+
+    fn f32(&mut self) -> io::Result<f32> {
+        self.array().map(f32::from_le_bytes)
+    }
+
+    fn fourcc(&mut self) -> io::Result<String> {
+        Ok(String::from_utf8_lossy(&self.array::<4>()?).into_owned())
+    }
+
+    fn skip(&mut self, n: usize, elem_size: usize) -> io::Result<()> {
+        let bytes = n
+            .checked_mul(elem_size)
+            .and_then(|b| i64::try_from(b).ok())
+            .ok_or_else(|| invalid_data(format!("vector of {} elements too large", n)))?;
+        self.r.seek(SeekFrom::Current(bytes))?;
+        Ok(())
+    }
+
+    /// Skip a length-prefixed vector, returning its length.
+    fn skip_vec(&mut self, elem_size: usize) -> io::Result<usize> {
+        let n = self.size()?;
+        self.skip(n, elem_size)?;
+        Ok(n)
+    }
+
+    fn size_vec(&mut self) -> io::Result<Vec<usize>> {
+        let n = self.size()?;
+        // grow incrementally so that a corrupted length
+        // does not trigger a huge allocation
+        let mut v = Vec::new();
+        for _ in 0..n {
+            v.push(self.size()?);
+        }
+        Ok(v)
+    }
+
+    fn header(
+        &mut self,
+        fourcc: String,
+        details: fn(&mut Self, &mut IndexInfo) -> Result<()>,
+    ) -> Result<IndexInfo> {
+        let d = self.i32()?;
+        let d = u32::try_from(d).map_err(|_| invalid_data(format!("invalid dimension {}", d)))?;
+        let ntotal = self.i64()?;
+        let ntotal = u64::try_from(ntotal)
+            .map_err(|_| invalid_data(format!("invalid number of vectors {}", ntotal)))?;
+        // two unused fields
+        self.i64()?;
+        self.i64()?;
+        let is_trained = self.bool()?;
+        let metric_type = self.u32()?;
+        let metric_arg = if metric_type > 1 {
+            Some(self.f32()?)
+        } else {
+            None
+        };
+        let mut info = IndexInfo {
+            fourcc,
+            d,
+            ntotal,
+            metric_type,
+            metric_arg,
+            is_trained,
+            code_size: None,
+            details: IndexDetails::Flat,
+        };
+        details(self, &mut info)?;
+        Ok(info)
+    }
+
+    /// Run `f` one nesting level deeper, failing beyond [`MAX_DEPTH`].
+    fn nested<T, E, F>(&mut self, f: F) -> std::result::Result<T, E>
+    where
+        E: From<io::Error>,
+        F: FnOnce(&mut Self) -> std::result::Result<T, E>,
+    {
+        if self.depth == MAX_DEPTH {
+            return Err(
+                invalid_data(format!("sections nested deeper than {} levels", MAX_DEPTH)).into(),
+            );
+        }
+        self.depth += 1;
+        let r = f(self);
+        self.depth -= 1;
+        r
+    }
+
+    fn index(&mut self) -> Result<IndexInfo> {
+        self.nested(Self::index_body)
+    }
+
+    fn index_body(&mut self) -> Result<IndexInfo> {
+        let fourcc = self.fourcc()?;
+        match fourcc.as_str() {
+            "IxFI" | "IxF2" | "IxFl" => self.header(fourcc, |p, info| {
+                // the codes are stored as a vector of floats
+                p.skip_vec(4)?;
+                info.code_size = Some(info.d as usize * 4);
+                Ok(())
+            }),
+            "IxHe" => self.header(fourcc, |p, info| {
+                let nbits = p.i32()?;
+                let rotate_data = p.bool()?;
+                let train_thresholds = p.bool()?;
+                p.skip_vec(4)?;
+                let code_size = p.i32()?;
+                p.transform()?;
+                p.skip_vec(1)?;
+                info.code_size = Some(code_size as usize);
+                info.details = IndexDetails::Lsh {
+                    nbits,
+                    rotate_data,
+                    train_thresholds,
+                };
+                Ok(())
+            }),
+            "IxSQ" => self.header(fourcc, |p, info| {
+                let (qtype, code_size) = p.scalar_quantizer()?;
+                p.skip_vec(1)?;
+                info.code_size = Some(code_size);
+                info.details = IndexDetails::ScalarQuantizer { qtype };
+                Ok(())
+            }),
+            "IxPq" => self.header(fourcc, |p, info| {
+                let (m, nbits) = p.product_quantizer()?;
+                p.skip_vec(1)?;
+                // search type, sign encoding and polysemous threshold
+                p.i32()?;
+                p.bool()?;
+                p.i32()?;
+                info.code_size = Some((m * nbits + 7) / 8);
+                info.details = IndexDetails::ProductQuantizer { m, nbits };
+                Ok(())
+            }),
+            "IwFl" => self.header(fourcc, |p, info| {
+                let (nlist, nprobe, quantizer) = p.ivf_header()?;
+                let invlists = p.inverted_lists()?;
+                info.code_size = Some(info.d as usize * 4);
+                info.details = IndexDetails::Ivf(IvfInfo {
+                    nlist,
+                    nprobe,
+                    quantizer,
+                    encoding: IvfEncoding::Flat,
+                    by_residual: None,
+                    invlists,
+                });
+                Ok(())
+            }),
+            "IwSq" => self.header(fourcc, |p, info| {
+                let (nlist, nprobe, quantizer) = p.ivf_header()?;
+                let (qtype, _) = p.scalar_quantizer()?;
+                let code_size = p.size()?;
+                let by_residual = p.bool()?;
+                let invlists = p.inverted_lists()?;
+                info.code_size = Some(code_size);
+                info.details = IndexDetails::Ivf(IvfInfo {
+                    nlist,
+                    nprobe,
+                    quantizer,
+                    encoding: IvfEncoding::ScalarQuantizer { qtype },
+                    by_residual: Some(by_residual),
+                    invlists,
+                });
+                Ok(())
+            }),
+            "IwPQ" => self.header(fourcc, |p, info| {
+                let (nlist, nprobe, quantizer) = p.ivf_header()?;
+                let by_residual = p.bool()?;
+                let code_size = p.size()?;
+                let (m, nbits) = p.product_quantizer()?;
+                let invlists = p.inverted_lists()?;
+                info.code_size = Some(code_size);
+                info.details = IndexDetails::Ivf(IvfInfo {
+                    nlist,
+                    nprobe,
+                    quantizer,
+                    encoding: IvfEncoding::ProductQuantizer { m, nbits },
+                    by_residual: Some(by_residual),
+                    invlists,
+                });
+                Ok(())
+            }),
+            "IxMp" | "IxM2" => self.header(fourcc, |p, info| {
+                let index = Box::new(p.index()?);
+                // the ID map
+                p.skip_vec(8)?;
+                info.details = if info.fourcc == "IxMp" {
+                    IndexDetails::IdMap { index }
+                } else {
+                    IndexDetails::IdMap2 { index }
+                };
+                Ok(())
+            }),
+            "IxPT" => self.header(fourcc, |p, info| {
+                let nt = p.i32()?;
+                let chain = (0..nt)
+                    .map(|_| p.transform())
+                    .collect::<io::Result<Vec<_>>>()?;
+                let index = Box::new(p.index()?);
+                info.details = IndexDetails::PreTransform { chain, index };
+                Ok(())
+            }),
+            "IxRF" => self.header(fourcc, |p, info| {
+                let base = Box::new(p.index()?);
+                let refine = Box::new(p.index()?);
+                let k_factor = p.f32()?;
+                info.details = IndexDetails::Refine {
+                    base,
+                    refine,
+                    k_factor,
+                };
+                Ok(())
+            }),
+            "IHNf" | "IHNp" | "IHNs" | "IHN2" => self.header(fourcc, |p, info| {
+                // level assignment probabilities, cumulative neighbor counts,
+                // levels, offsets and neighbors
+                p.skip_vec(8)?;
+                p.skip_vec(4)?;
+                p.skip_vec(4)?;
+                p.skip_vec(8)?;
+                p.skip_vec(4)?;
+                // entry point
+                p.i32()?;
+                let max_level = p.i32()?;
+                let ef_construction = p.i32()?;
+                let ef_search = p.i32()?;
+                // unused upper beam
+                p.i32()?;
+                let storage = Box::new(p.index()?);
+                info.code_size = storage.code_size;
+                info.details = IndexDetails::Hnsw {
+                    max_level,
+                    ef_construction,
+                    ef_search,
+                    storage,
+                };
+                Ok(())
+            }),
+            _ => Err(invalid_data(format!("unsupported index type {:?}", fourcc)).into()),
+        }
+    }
+
+    fn ivf_header(&mut self) -> Result<(usize, usize, Box<IndexInfo>)> {
+        let nlist = self.size()?;
+        let nprobe = self.size()?;
+        let quantizer = Box::new(self.index()?);
+        // direct map
+        let map_type = self.array::<1>()?[0];
+        self.skip_vec(8)?;
+        if map_type == DIRECT_MAP_HASHTABLE {
+            self.skip_vec(16)?;
+        }
+        Ok((nlist, nprobe, quantizer))
+    }
+
+    fn scalar_quantizer(&mut self) -> io::Result<(u32, usize)> {
+        let qtype = self.u32()?;
+        // range statistic and its argument
+        self.u32()?;
+        self.f32()?;
+        // dimension
+        self.size()?;
+        let code_size = self.size()?;
+        // trained parameters
+        self.skip_vec(4)?;
+        Ok((qtype, code_size))
+    }
+
+    fn product_quantizer(&mut self) -> io::Result<(usize, usize)> {
+        // dimension
+        self.size()?;
+        let m = self.size()?;
+        let nbits = self.size()?;
+        // centroids
+        self.skip_vec(4)?;
+        Ok((m, nbits))
+    }
+
+    fn inverted_lists(&mut self) -> io::Result<Option<InvertedListsInfo>> {
+        let fourcc = self.fourcc()?;
+        match fourcc.as_str() {
+            "il00" => Ok(None),
+            "ilar" => {
+                let nlist = self.size()?;
+                let code_size = self.size()?;
+                let list_type = self.fourcc()?;
+                let list_sizes = match list_type.as_str() {
+                    "full" => self.size_vec()?,
+                    "sprs" => self.sparse_list_sizes(nlist)?,
+                    _ => {
+                        return Err(invalid_data(format!(
+                            "unsupported inverted list layout {:?}",
+                            list_type
+                        )))
+                    }
+                };
+                if list_sizes.len() != nlist {
+                    return Err(invalid_data("invalid number of inverted lists".into()));
+                }
+                // codes and IDs of each list
+                for &n in &list_sizes {
+                    self.skip(n, code_size + 8)?;
+                }
+                Ok(Some(InvertedListsInfo {
+                    fourcc,
+                    nlist,
+                    code_size,
+                    list_sizes,
+                }))
+            }
+            "ilod" => {
+                let nlist = self.size()?;
+                let code_size = self.size()?;
+                // each list is described by its size, capacity and offset
+                let n = self.size()?;
+                let mut list_sizes = Vec::new();
+                for _ in 0..n {
+                    list_sizes.push(self.size()?);
+                    self.size()?;
+                    self.size()?;
+                }
+                // free slots, file name and total size
+                self.skip_vec(16)?;
+                self.skip_vec(1)?;
+                self.size()?;
+                Ok(Some(InvertedListsInfo {
+                    fourcc,
+                    nlist,
+                    code_size,
+                    list_sizes,
+                }))
+            }
+            _ => Err(invalid_data(format!(
+                "unsupported inverted lists type {:?}",
+                fourcc
+            ))),
+        }
+    }
+
+    /// Read the sizes of the non-empty lists out of `nlist` inverted lists,
+    /// stored as pairs of list number and size in increasing list order.
+    fn sparse_list_sizes(&mut self, nlist: usize) -> io::Result<Vec<usize>> {
+        let n = self.size()?;
+        if n % 2 != 0 {
+            return Err(invalid_data("invalid sparse list sizes".into()));
+        }
+        // grow incrementally, and only as far as the list numbers read so far,
+        // so that a corrupted number of lists does not abort on allocation
+        let mut list_sizes = Vec::new();
+        for _ in 0..n / 2 {
+            let list_no = self.size()?;
+            let size = self.size()?;
+            if list_no < list_sizes.len() || list_no >= nlist {
+                return Err(invalid_data("invalid sparse list sizes".into()));
+            }
+            zero_extend(&mut list_sizes, list_no)?;
+            list_sizes.push(size);
+        }
+        zero_extend(&mut list_sizes, nlist)?;
+        Ok(list_sizes)
+    }
+
+    fn transform(&mut self) -> io::Result<TransformInfo> {
+        self.nested(Self::transform_body)
+    }
+
+    fn transform_body(&mut self) -> io::Result<TransformInfo> {
+        let fourcc = self.fourcc()?;
+        match fourcc.as_str() {
+            "rrot" | "Pcam" | "PcAm" | "LTra" | "Viqm" => {
+                match fourcc.as_str() {
+                    "Pcam" | "PcAm" => {
+                        // eigen power, epsilon (newer format only),
+                        // random rotation and balanced bins
+                        self.f32()?;
+                        if fourcc == "PcAm" {
+                            self.f32()?;
+                        }
+                        self.bool()?;
+                        self.bool()?;
+                        // mean, eigenvalues and PCA matrix
+                        self.skip_vec(4)?;
+                        self.skip_vec(4)?;
+                        self.skip_vec(4)?;
+                    }
+                    "Viqm" => {
+                        // maximum number of iterations and seed
+                        self.i32()?;
+                        self.i32()?;
+                    }
+                    _ => {}
+                }
+                // bias flag, matrix and bias vector
+                self.bool()?;
+                self.skip_vec(4)?;
+                self.skip_vec(4)?;
+            }
+            "RmDT" => {
+                self.skip_vec(4)?;
+            }
+            "VNrm" => {
+                self.f32()?;
+            }
+            "VCnt" => {
+                self.skip_vec(4)?;
+            }
+            "Viqt" => {
+                self.skip_vec(4)?;
+                self.bool()?;
+                self.transform()?;
+                self.transform()?;
+            }
+            _ => {
+                return Err(invalid_data(format!(
+                    "unsupported vector transform type {:?}",
+                    fourcc
+                )))
+            }
+        }
+        let d_in = self.i32()?;
+        let d_out = self.i32()?;
+        let is_trained = self.bool()?;
+        Ok(TransformInfo {
+            fourcc,
+            d_in,
+            d_out,
+            is_trained,
+        })
+    }
+}
@@ -0,0 +1,341 @@
+//! Bridge between Rust I/O streams and C `FILE` streams.
+//!
+//! The Faiss C API only reads and writes indexes through `FILE` pointers.
+//! This module builds custom C streams whose operations are forwarded to a
+//! Rust [`Read`] or [`Write`] implementation, using `fopencookie` on Linux
+//! and `funopen` on macOS and the BSDs.
+//!
+//! [`Read`]: std::io::Read
+//! [`Write`]: std::io::Write
+
+use crate::error::{Error, Result};
+use faiss_sys::{fclose, FILE};
+use std::any::Any;
+use std::io::{self, Read, Write};
+use std::os::raw::{c_char, c_void};
+use std::panic::{self, AssertUnwindSafe};
+
+/// State shared with the stream callbacks.
+struct Cookie<S> {
+    stream: S,
+    /// the first I/O error reported by the Rust stream
+    error: Option<io::Error>,
+    /// the payload of a panic caught inside a callback
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+impl<S> Cookie<S> {
+    /// Run a callback operation, catching I/O errors and panics so that
+    /// they do not unwind through native code.
+    fn guard<F>(&mut self, f: F) -> Option<usize>
+    where
+        F: FnOnce(&mut S) -> io::Result<usize>,
+    {
+        if self.error.is_some() || self.panic.is_some() {
+            return None;
+        }
+        let stream = &mut self.stream;
+        match panic::catch_unwind(AssertUnwindSafe(|| f(stream))) {
+            Ok(Ok(n)) => Some(n),
+            Ok(Err(e)) => {
+                self.error = Some(e);
+                None
+            }
+            Err(payload) => {
+                self.panic = Some(payload);
+                None
+            }
+        }
+    }
+}
+
+fn read_retrying<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match reader.read(buf) {
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            r => return r,
+        }
+    }
+}
+
+unsafe fn do_read<R: Read>(cookie: *mut c_void, buf: *mut c_char, size: usize) -> Option<usize> {
+    let cookie = &mut *(cookie as *mut Cookie<R>);
+    let buf = std::slice::from_raw_parts_mut(buf as *mut u8, size);
+    cookie.guard(|reader| read_retrying(reader, buf))
+}
+
+unsafe fn do_write<W: Write>(
+    cookie: *mut c_void,
+    buf: *const c_char,
+    size: usize,
+) -> Option<usize> {
+    let cookie = &mut *(cookie as *mut Cookie<W>);
+    let buf = std::slice::from_raw_parts(buf as *const u8, size);
+    cookie.guard(|writer| writer.write_all(buf).map(|_| size))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod sys {
+    use super::{do_read, do_write, non_null, FILE};
+    use std::io::{self, Read, Write};
+    use std::os::raw::{c_char, c_int, c_void};
+
+    type ReadFn = unsafe extern "C" fn(*mut c_void, *mut c_char, usize) -> isize;
+    type WriteFn = unsafe extern "C" fn(*mut c_void, *const c_char, usize) -> isize;
+    type SeekFn = unsafe extern "C" fn(*mut c_void, *mut i64, c_int) -> c_int;
+    type CloseFn = unsafe extern "C" fn(*mut c_void) -> c_int;
+
+    #[repr(C)]
+    struct CookieIoFunctions {
+        read: Option<ReadFn>,
+        write: Option<WriteFn>,
+        seek: Option<SeekFn>,
+        close: Option<CloseFn>,
+    }
+
+    extern "C" {
+        fn fopencookie(
+            cookie: *mut c_void,
+            mode: *const c_char,
+            io_funcs: CookieIoFunctions,
+        ) -> *mut FILE;
+    }
+
+    unsafe extern "C" fn read_fn<R: Read>(
+        cookie: *mut c_void,
+        buf: *mut c_char,
+        size: usize,
+    ) -> isize {
+        do_read::<R>(cookie, buf, size).map_or(-1, |n| n as isize)
+    }
+
+    unsafe extern "C" fn write_fn<W: Write>(
+        cookie: *mut c_void,
+        buf: *const c_char,
+        size: usize,
+    ) -> isize {
+        // glibc expects 0 (rather than a negative value) on write errors
+        do_write::<W>(cookie, buf, size).map_or(0, |n| n as isize)
+    }
+
+    pub(super) unsafe fn open_read<R: Read>(cookie: *mut c_void) -> io::Result<*mut FILE> {
+        let funcs = CookieIoFunctions {
+            read: Some(read_fn::<R>),
+            write: None,
+            seek: None,
+            close: None,
+        };
+        non_null(fopencookie(
+            cookie,
+            b"rb\0".as_ptr() as *const c_char,
+            funcs,
+        ))
+    }
+
+    pub(super) unsafe fn open_write<W: Write>(cookie: *mut c_void) -> io::Result<*mut FILE> {
+        let funcs = CookieIoFunctions {
+            read: None,
+            write: Some(write_fn::<W>),
+            seek: None,
+            close: None,
+        };
+        non_null(fopencookie(
+            cookie,
+            b"wb\0".as_ptr() as *const c_char,
+            funcs,
+        ))
+    }
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+))]
+mod sys {
+    use super::{do_read, do_write, non_null, FILE};
+    use std::io::{self, Read, Write};
+    use std::os::raw::{c_char, c_int, c_void};
+
+    type ReadFn = unsafe extern "C" fn(*mut c_void, *mut c_char, c_int) -> c_int;
+    type WriteFn = unsafe extern "C" fn(*mut c_void, *const c_char, c_int) -> c_int;
+    type SeekFn = unsafe extern "C" fn(*mut c_void, i64, c_int) -> i64;
+    type CloseFn = unsafe extern "C" fn(*mut c_void) -> c_int;
+
+    extern "C" {
+        fn funopen(
+            cookie: *const c_void,
+            readfn: Option<ReadFn>,
+            writefn: Option<WriteFn>,
+            seekfn: Option<SeekFn>,
+            closefn: Option<CloseFn>,
+        ) -> *mut FILE;
+    }
+
+    unsafe extern "C" fn read_fn<R: Read>(
+        cookie: *mut c_void,
+        buf: *mut c_char,
+        size: c_int,
+    ) -> c_int {
+        do_read::<R>(cookie, buf, size as usize).map_or(-1, |n| n as c_int)
+    }
+
+    unsafe extern "C" fn write_fn<W: Write>(
+        cookie: *mut c_void,
+        buf: *const c_char,
+        size: c_int,
+    ) -> c_int {
+        do_write::<W>(cookie, buf, size as usize).map_or(-1, |n| n as c_int)
+    }
+
+    pub(super) unsafe fn open_read<R: Read>(cookie: *mut c_void) -> io::Result<*mut FILE> {
+        non_null(funopen(cookie, Some(read_fn::<R>), None, None, None))
+    }
+
+    pub(super) unsafe fn open_write<W: Write>(cookie: *mut c_void) -> io::Result<*mut FILE> {
+        non_null(funopen(cookie, None, Some(write_fn::<W>), None, None))
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+)))]
+mod sys {
+    use super::FILE;
+    use std::io::{self, Read, Write};
+    use std::os::raw::c_void;
+
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "index streams are not supported on this platform",
+        )
+    }
+
+    pub(super) unsafe fn open_read<R: Read>(_cookie: *mut c_void) -> io::Result<*mut FILE> {
+        Err(unsupported())
+    }
+
+    pub(super) unsafe fn open_write<W: Write>(_cookie: *mut c_void) -> io::Result<*mut FILE> {
+        Err(unsupported())
+    }
+}
+
+#[allow(dead_code)]
+fn non_null(file: *mut FILE) -> io::Result<*mut FILE> {
+    if file.is_null() {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(file)
+    }
+}
+
+/// A C stream backed by a Rust reader or writer.
+struct CFile<S> {
+    file: *mut FILE,
+    cookie: *mut Cookie<S>,
+}
+
+impl<S> CFile<S> {
+    fn new(stream: S, open: unsafe fn(*mut c_void) -> io::Result<*mut FILE>) -> Result<Self> {
+        let cookie = Box::into_raw(Box::new(Cookie {
+            stream,
+            error: None,
+            panic: None,
+        }));
+        match unsafe { open(cookie as *mut c_void) } {
+            Ok(file) => Ok(CFile { file, cookie }),
+            Err(e) => {
+                // safety: the stream was not created,
+                // so nothing else refers to the cookie
+                drop(unsafe { Box::from_raw(cookie) });
+                Err(Error::from(e))
+            }
+        }
+    }
+
+    /// Close the C stream (flushing any buffered output) and recover the
+    /// Rust stream, along with the first I/O error seen by the callbacks.
+    ///
+    /// If a callback panicked, the panic is resumed here.
+    fn close(self) -> (S, Option<io::Error>) {
+        let file = self.file;
+        let cookie = self.cookie;
+        std::mem::forget(self);
+        unsafe {
+            let code = fclose(file);
+            // safety: the C stream is closed, so the callbacks no longer
+            // hold on to the cookie
+            let cookie = Box::from_raw(cookie);
+            if let Some(payload) = cookie.panic {
+                panic::resume_unwind(payload);
+            }
+            let error = match cookie.error {
+                Some(e) => Some(e),
+                None if code != 0 => Some(io::Error::new(
+                    io::ErrorKind::Other,
+                    "failed to flush index stream",
+                )),
+                None => None,
+            };
+            (cookie.stream, error)
+        }
+    }
+}
+
+impl<S> Drop for CFile<S> {
+    fn drop(&mut self) {
+        unsafe {
+            fclose(self.file);
+            drop(Box::from_raw(self.cookie));
+        }
+    }
+}
+
+/// Call `f` with a C stream whose output is forwarded to `writer`.
+///
+/// Errors raised by `writer` take precedence over the native error which
+/// they caused.
+pub(crate) fn with_write_stream<W, F>(writer: W, f: F) -> Result<()>
+where
+    W: Write,
+    F: FnOnce(*mut FILE) -> Result<()>,
+{
+    let file = CFile::new(writer, sys::open_write::<W>)?;
+    let r = f(file.file);
+    let (mut writer, error) = file.close();
+    if let Some(e) = error {
+        return Err(Error::from(e));
+    }
+    r?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Call `f` with a C stream whose input is taken from `reader`.
+///
+/// Errors raised by `reader` take precedence over the native error which
+/// they caused.
+pub(crate) fn with_read_stream<R, T, F>(reader: R, f: F) -> Result<T>
+where
+    R: Read,
+    F: FnOnce(*mut FILE) -> Result<T>,
+{
+    let file = CFile::new(reader, sys::open_read::<R>)?;
+    let r = f(file.file);
+    let (_, error) = file.close();
+    if let Some(e) = error {
+        return Err(Error::from(e));
+    }
+    r
+}
@@ -0,0 +1,685 @@
+//! Interface common to all inverted file (IVF) indexes.
+//!
+//! The [`IvfIndex`] trait is implemented by every IVF index type in this
+//! crate. For IVF index types without a dedicated wrapper (such as those
+//! created with [`index_factory`]), an [`IndexImpl`] can be converted with
+//! [`IndexImpl::into_ivf`] into an [`IVFIndexImpl`], which implements it too.
+//!
+//! [`index_factory`]: crate::index::index_factory
+
+use super::*;
+
+use crate::error::Result;
+use crate::faiss_try;
+use crate::index::flat::FlatIndexImpl;
+use crate::index::ivf_flat::{IVFFlatIndexImpl, TrainType};
+use crate::index::scalar_quantizer::IVFScalarQuantizerIndexImpl;
+use std::marker::PhantomData;
+use std::mem::{self, ManuallyDrop};
+use std::ops::Deref;
+use std::os::raw::c_int;
+
+/// Common interface of inverted file indexes.
+///
+/// All methods are implemented on top of [`ivf_inner_ptr`],
+/// so implementors only need to provide the pointer to the native IVF index.
+///
+/// [`ivf_inner_ptr`]: IvfIndex::ivf_inner_ptr
+pub trait IvfIndex: NativeIndex {
+    /// The type of the coarse quantizer.
+    type Quantizer: TryFromInnerPtr;
+
+    /// Retrieve a pointer to the native IVF index object.
+    fn ivf_inner_ptr(&self) -> *mut FaissIndexIVF;
+
+    /// Get number of inverted lists
+    fn nlist(&self) -> u32 {
+        unsafe { faiss_IndexIVF_nlist(self.ivf_inner_ptr()) as u32 }
+    }
+
+    /// Get number of probes at query time
+    fn nprobe(&self) -> u32 {
+        unsafe { faiss_IndexIVF_nprobe(self.ivf_inner_ptr()) as u32 }
+    }
+
+    /// Set number of probes at query time
+    fn set_nprobe(&mut self, value: u32) {
+        unsafe {
+            faiss_IndexIVF_set_nprobe(self.ivf_inner_ptr(), value as usize);
+        }
+    }
+
+    /// Get train type
+    fn train_type(&self) -> Option<TrainType> {
+        unsafe {
+            let code = faiss_IndexIVF_quantizer_trains_alone(self.ivf_inner_ptr());
+            TrainType::from_code(code)
+        }
+    }
+
+    /// Get the number of vectors in the given inverted list.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `list_no` is not lower than the number of inverted lists.
+    fn list_size(&self, list_no: u32) -> usize {
+        assert!(
+            list_no < self.nlist(),
+            "list number {} out of bounds",
+            list_no
+        );
+        unsafe { faiss_IndexIVF_get_list_size(self.ivf_inner_ptr(), list_no as usize) }
+    }
+
+    /// Get the number of vectors in each inverted list.
+    fn list_sizes(&self) -> Vec<usize> {
+        (0..self.nlist()).map(|i| self.list_size(i)).collect()
+    }
+
+    /// Get the IDs of the vectors in the given inverted list.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `list_no` is not lower than the number of inverted lists.
+    fn list_ids(&self, list_no: u32) -> Vec<Idx> {
+        let size = self.list_size(list_no);
+        let mut ids = vec![Idx::none(); size];
+        if size > 0 {
+            unsafe {
+                faiss_IndexIVF_invlists_get_ids(
+                    self.ivf_inner_ptr(),
+                    list_no as usize,
+                    ids.as_mut_ptr() as *mut idx_t,
+                );
+            }
+        }
+        ids
+    }
+
+    /// Check the imbalance factor of the inverted lists
+    /// (1 if perfectly balanced, greater than 1 otherwise).
+    fn imbalance_factor(&self) -> f64 {
+        unsafe { faiss_IndexIVF_imbalance_factor(self.ivf_inner_ptr()) }
+    }
+
+    /// Print some statistics about the inverted lists to standard output.
+    fn print_stats(&self) {
+        unsafe { faiss_IndexIVF_print_stats(self.ivf_inner_ptr()) }
+    }
+
+    /// Borrow the coarse quantizer of this index.
+    ///
+    /// # Error
+    ///
+    /// Returns `Error::BadCast` if the quantizer
+    /// is not of the expected quantizer type.
+    fn quantizer(&self) -> Result<QuantizerRef<'_, Self::Quantizer>> {
+        unsafe {
+            let inner = faiss_IndexIVF_quantizer(self.ivf_inner_ptr());
+            let index = Self::Quantizer::try_from_inner_ptr(inner)?;
+            Ok(QuantizerRef {
+                index: ManuallyDrop::new(index),
+                phantom: PhantomData,
+            })
+        }
+    }
+
+    /// Initialize (or reset) the direct map, which is required in order to
+    /// reconstruct vectors from the index.
+    /// If `new_maintain` is false, the direct map is discarded instead.
+    fn make_direct_map(&mut self, new_maintain: bool) -> Result<()> {
+        unsafe {
+            faiss_try(faiss_IndexIVF_make_direct_map(
+                self.ivf_inner_ptr(),
+                c_int::from(new_maintain),
+            ))?;
+            Ok(())
+        }
+    }
+
+    /// Perform a search for the `k` nearest neighbors of the given queries,
+    /// using precomputed coarse quantizer assignments.
+    ///
+    /// For each query, `assign` must contain the `nprobe` inverted lists to
+    /// visit (as obtained by searching the coarse quantizer), and
+    /// `centroid_dis` the distances to the respective centroids.
+    /// If `store_pairs` is true, each resulting label holds the inverted list
+    /// number and the offset within that list (in its upper and lower 32 bits)
+    /// instead of the vector ID.
+    ///
+    /// # Error
+    ///
+    /// Returns `Error::BadLength` if the length of `query` is not a multiple
+    /// of the dimensionality, or if `assign` or `centroid_dis` do not contain
+    /// `nprobe` values per query, or a native error if the search fails.
+    fn search_preassigned(
+        &self,
+        query: &[f32],
+        k: usize,
+        assign: &[Idx],
+        centroid_dis: &[f32],
+        store_pairs: bool,
+    ) -> Result<SearchResult> {
+        let d = self.d() as usize;
+        let nprobe = self.nprobe() as usize;
+        let nq = query.len() / d;
+        if nq * d != query.len() || assign.len() != nq * nprobe || centroid_dis.len() != nq * nprobe
+        {
+            return Err(Error::BadLength);
+        }
+        unsafe {
+            let mut distances = vec![0_f32; k * nq];
+            let mut labels = vec![Idx::none(); k * nq];
+            faiss_try(faiss_IndexIVF_search_preassigned(
+                self.ivf_inner_ptr(),
+                nq as idx_t,
+                query.as_ptr(),
+                k as idx_t,
+                assign.as_ptr() as *const idx_t,
+                centroid_dis.as_ptr(),
+                distances.as_mut_ptr(),
+                labels.as_mut_ptr() as *mut _,
+                c_int::from(store_pairs),
+            ))?;
+            Ok(SearchResult { distances, labels })
+        }
+    }
+
+    /// Move all entries of `other` into this index, leaving `other` empty.
+    /// `add_id` is added to the IDs of all moved entries
+    /// (for sequential IDs, this would be the current `ntotal` of this index).
+    ///
+    /// Both indexes should share the same coarse quantizer centroids.
+    ///
+    /// # Error
+    ///
+    /// Returns `Error::IndexMismatch` if the two indexes differ in
+    /// dimensionality or number of inverted lists, or a native error
+    /// if the indexes cannot be merged.
+    fn merge_from(&mut self, other: &mut Self, add_id: Idx) -> Result<()>
+    where
+        Self: Sized,
+    {
+        check_compatible(self, other)?;
+        unsafe {
+            faiss_try(faiss_IndexIVF_merge_from(
+                self.ivf_inner_ptr(),
+                other.ivf_inner_ptr(),
+                add_id.to_native(),
+            ))?;
+            Ok(())
+        }
+    }
+
+    /// Copy a subset of the entries of this index into `other`.
+    ///
+    /// Both indexes should share the same coarse quantizer centroids.
+    ///
+    /// # Error
+    ///
+    /// Returns `Error::IndexMismatch` if the two indexes differ in
+    /// dimensionality or number of inverted lists,
+    /// `Error::BadArgument` if the subset is [`Subset::IdModulo`]
+    /// with a modulus which is not positive,
+    /// or a native error if the entries cannot be copied.
+    fn copy_subset_to(&self, other: &mut Self, subset: Subset) -> Result<()>
+    where
+        Self: Sized,
+    {
+        check_compatible(self, other)?;
+        let (subset_type, a1, a2) = match subset {
+            Subset::IdRange { start, end } => (0, start, end),
+            Subset::IdModulo { modulus, remainder } => {
+                if modulus <= 0 {
+                    return Err(Error::BadArgument);
+                }
+                (1, modulus, remainder)
+            }
+            Subset::Lists { start, end } => (2, start, end),
+        };
+        unsafe {
+            faiss_try(faiss_IndexIVF_copy_subset_to(
+                self.ivf_inner_ptr(),
+                other.ivf_inner_ptr(),
+                subset_type,
+                a1,
+                a2,
+            ))?;
+            Ok(())
+        }
+    }
+}
+
+fn check_compatible<I: IvfIndex>(a: &I, b: &I) -> Result<()> {
+    if a.d() != b.d() || a.nlist() != b.nlist() {
+        return Err(Error::IndexMismatch);
+    }
+    Ok(())
+}
+
+/// A selection of entries of an IVF index,
+/// used in [`IvfIndex::copy_subset_to`].
+#[derive(Debug, Copy, Clone, Eq, Hash, PartialEq)]
+pub enum Subset {
+    /// The entries with an ID in the range `[start, end)`
+    IdRange {
+        /// the first ID in the range
+        start: i64,
+        /// the end of the range (exclusive)
+        end: i64,
+    },
+    /// The entries with an ID such that `id % modulus == remainder`
+    IdModulo {
+        /// the modulus, which must be positive
+        modulus: i64,
+        /// the expected remainder
+        remainder: i64,
+    },
+    /// The entries at positions `[start, end)` when all inverted lists
+    /// are laid out one after the other.
+    /// The window is spread over the lists in proportion to their sizes,
+    /// so that each list contributes a contiguous run of its entries
+    /// and about `end - start` entries are selected in total.
+    Lists {
+        /// the first position in the window
+        start: i64,
+        /// the end of the window (exclusive)
+        end: i64,
+    },
+}
+
+/// Search statistics of IVF indexes.
+///
+/// Faiss accumulates these statistics in process-wide counters,
+/// which are updated by searches on all IVF indexes in all threads.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct IvfStats {
+    /// number of queries
+    pub nq: usize,
+    /// number of inverted lists scanned
+    pub nlist: usize,
+    /// number of distances computed
+    pub ndis: usize,
+    /// number of times that the result heaps were updated
+    pub nheap_updates: usize,
+    /// time spent in the coarse quantizer, in milliseconds
+    pub quantization_time: f64,
+    /// time spent scanning the inverted lists, in milliseconds
+    pub search_time: f64,
+}
+
+impl IvfStats {
+    /// Obtain the current values of the global IVF statistics.
+    pub fn get() -> Self {
+        unsafe { IvfStats::from(*faiss_get_indexIVF_stats()) }
+    }
+
+    /// Reset the global IVF statistics to zero.
+    pub fn reset() {
+        unsafe { faiss_IndexIVFStats_reset(faiss_get_indexIVF_stats()) }
+    }
+}
+
+impl From<FaissIndexIVFStats> for IvfStats {
+    fn from(stats: FaissIndexIVFStats) -> Self {
+        IvfStats {
+            nq: stats.nq,
+            nlist: stats.nlist,
+            ndis: stats.ndis,
+            nheap_updates: stats.nheap_updates,
+            quantization_time: stats.quantization_time,
+            search_time: stats.search_time,
+        }
+    }
+}
+
+/// The difference between two snapshots of the statistics.
+///
+/// Each field saturates at zero, in case the counters
+/// were reset in between the two snapshots.
+impl std::ops::Sub for IvfStats {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        IvfStats {
+            nq: self.nq.saturating_sub(rhs.nq),
+            nlist: self.nlist.saturating_sub(rhs.nlist),
+            ndis: self.ndis.saturating_sub(rhs.ndis),
+            nheap_updates: self.nheap_updates.saturating_sub(rhs.nheap_updates),
+            quantization_time: (self.quantization_time - rhs.quantization_time).max(0.),
+            search_time: (self.search_time - rhs.search_time).max(0.),
+        }
+    }
+}
+
+/// Run `f` and collect the IVF statistics accumulated while it ran.
+///
+/// The global statistics are not reset, so other users of the counters
+/// are unaffected. Note however that searches running concurrently in
+/// other threads are accounted for as well.
+pub fn with_ivf_stats<F, R>(f: F) -> (R, IvfStats)
+where
+    F: FnOnce() -> R,
+{
+    let before = IvfStats::get();
+    let r = f();
+    (r, IvfStats::get() - before)
+}
+
+/// A borrowed coarse quantizer of an IVF index.
+///
+/// The quantizer remains owned by the IVF index,
+/// and is only accessible through a shared reference.
+#[derive(Debug)]
+pub struct QuantizerRef<'a, Q> {
+    index: ManuallyDrop<Q>,
+    phantom: PhantomData<&'a Q>,
+}
+
+impl<Q> Deref for QuantizerRef<'_, Q> {
+    type Target = Q;
+
+    fn deref(&self) -> &Q {
+        &self.index
+    }
+}
+
+impl IvfIndex for IVFFlatIndexImpl {
+    type Quantizer = FlatIndexImpl;
+
+    fn ivf_inner_ptr(&self) -> *mut FaissIndexIVF {
+        self.inner_ptr()
+    }
+}
+
+impl<Q: TryFromInnerPtr> IvfIndex for IVFScalarQuantizerIndexImpl<Q> {
+    type Quantizer = Q;
+
+    fn ivf_inner_ptr(&self) -> *mut FaissIndexIVF {
+        self.inner_ptr()
+    }
+}
+
+/// Alias for the native implementation of a generic IVF index.
+pub type IVFIndex = IVFIndexImpl;
+
+/// Native implementation of an IVF index of any kind.
+#[derive(Debug)]
+pub struct IVFIndexImpl {
+    inner: *mut FaissIndexIVF,
+}
+
+unsafe impl Send for IVFIndexImpl {}
+unsafe impl Sync for IVFIndexImpl {}
+
+impl CpuIndex for IVFIndexImpl {}
+
+impl Drop for IVFIndexImpl {
+    fn drop(&mut self) {
+        unsafe {
+            faiss_IndexIVF_free(self.inner);
+        }
+    }
+}
+
+impl NativeIndex for IVFIndexImpl {
+    fn inner_ptr(&self) -> *mut FaissIndex {
+        self.inner
+    }
+}
+
+impl FromInnerPtr for IVFIndexImpl {
+    unsafe fn from_inner_ptr(inner_ptr: *mut FaissIndex) -> Self {
+        IVFIndexImpl {
+            inner: inner_ptr as *mut FaissIndexIVF,
+        }
+    }
+}
+
+impl TryFromInnerPtr for IVFIndexImpl {
+    unsafe fn try_from_inner_ptr(inner_ptr: *mut FaissIndex) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        // safety: `inner_ptr` is documented to be a valid pointer to an index,
+        // so the dynamic cast should be safe.
+        #[allow(unused_unsafe)]
+        unsafe {
+            let new_inner = faiss_IndexIVF_cast(inner_ptr);
+            if new_inner.is_null() {
+                Err(Error::BadCast)
+            } else {
+                Ok(IVFIndexImpl { inner: new_inner })
+            }
+        }
+    }
+}
+
+impl IvfIndex for IVFIndexImpl {
+    type Quantizer = IndexImpl;
+
+    fn ivf_inner_ptr(&self) -> *mut FaissIndexIVF {
+        self.inner
+    }
+}
+
+impl_native_index!(IVFIndexImpl);
+
+impl TryClone for IVFIndexImpl {
+    fn try_clone(&self) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        try_clone_from_inner_ptr(self)
+    }
+}
+
+impl_concurrent_index!(IVFIndexImpl);
+
+impl IndexImpl {
+    /// Attempt a dynamic cast of an index to the generic IVF index type.
+    pub fn into_ivf(self) -> Result<IVFIndexImpl> {
+        unsafe {
+            let index = IVFIndexImpl::try_from_inner_ptr(self.inner_ptr())?;
+            mem::forget(self);
+            Ok(index)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{with_ivf_stats, IvfIndex, IvfStats, Subset};
+    use crate::error::Error;
+    use crate::index::flat::FlatIndexImpl;
+    use crate::index::ivf_flat::IVFFlatIndexImpl;
+    use crate::index::scalar_quantizer::{IVFScalarQuantizerIndexImpl, QuantizerType};
+    use crate::index::{index_factory, ConcurrentIndex, Idx, Index, TryClone};
+    use crate::MetricType;
+
+    const D: u32 = 8;
+
+    fn some_data() -> Vec<f32> {
+        (0..D as usize * 64)
+            .map(|i| ((i * 7) % 23) as f32 - 11.)
+            .collect()
+    }
+
+    #[test]
+    fn ivf_flat_lists() {
+        let q = FlatIndexImpl::new_l2(D).unwrap();
+        let mut index = IVFFlatIndexImpl::new_l2(q, D, 4).unwrap();
+        let data = some_data();
+        index.train(&data).unwrap();
+        index.add(&data).unwrap();
+
+        assert_eq!(index.nlist(), 4);
+        let sizes = index.list_sizes();
+        assert_eq!(sizes.len(), 4);
+        assert_eq!(sizes.iter().sum::<usize>(), 64);
+        let mut ids: Vec<_> = (0..4).flat_map(|i| index.list_ids(i)).collect();
+        ids.sort_by_key(|id| id.get());
+        assert_eq!(ids, (0..64).map(Idx::new).collect::<Vec<_>>());
+        assert!(index.imbalance_factor() >= 1.);
+
+        let quantizer = index.quantizer().unwrap();
+        assert_eq!(quantizer.d(), D);
+        assert_eq!(quantizer.ntotal(), 4);
+    }
+
+    #[test]
+    fn ivf_sq_nprobe() {
+        let q = FlatIndexImpl::new_l2(D).unwrap();
+        let mut index =
+            IVFScalarQuantizerIndexImpl::new_l2(q, D, QuantizerType::QT_8bit, 2).unwrap();
+        index.set_nprobe(2);
+        assert_eq!(index.nprobe(), 2);
+        assert_eq!(index.quantizer().unwrap().ntotal(), 0);
+    }
+
+    #[test]
+    fn ivf_flat_merge_and_split() {
+        let q = FlatIndexImpl::new_l2(D).unwrap();
+        let mut index = IVFFlatIndexImpl::new_l2(q, D, 4).unwrap();
+        let data = some_data();
+        index.train(&data).unwrap();
+        let mut shard = index.try_clone().unwrap();
+        index.add(&data[..D as usize * 32]).unwrap();
+        shard.add(&data[D as usize * 32..]).unwrap();
+
+        index.merge_from(&mut shard, Idx::new(32)).unwrap();
+        assert_eq!(index.ntotal(), 64);
+        assert_eq!(shard.ntotal(), 0);
+        let result = index
+            .search(&data[D as usize * 40..D as usize * 41], 1)
+            .unwrap();
+        assert_eq!(result.labels, vec![Idx::new(40)]);
+
+        index
+            .copy_subset_to(&mut shard, Subset::IdRange { start: 8, end: 24 })
+            .unwrap();
+        assert_eq!(shard.ntotal(), 16);
+        let mut odd = index.try_clone().unwrap();
+        odd.reset().unwrap();
+        index
+            .copy_subset_to(
+                &mut odd,
+                Subset::IdModulo {
+                    modulus: 2,
+                    remainder: 1,
+                },
+            )
+            .unwrap();
+        assert_eq!(odd.ntotal(), 32);
+        assert!(odd.list_ids(0).iter().all(|id| id.get().unwrap() % 2 == 1));
+        assert_eq!(
+            index.copy_subset_to(
+                &mut odd,
+                Subset::IdModulo {
+                    modulus: 0,
+                    remainder: 0,
+                },
+            ),
+            Err(Error::BadArgument)
+        );
+
+        let mut window = index.try_clone().unwrap();
+        window.reset().unwrap();
+        index
+            .copy_subset_to(&mut window, Subset::Lists { start: 16, end: 48 })
+            .unwrap();
+        // rounding may shift the bounds within each list by one entry
+        let copied = window.ntotal() as i64;
+        let nlist = i64::from(index.nlist());
+        assert!((copied - 32).abs() <= nlist, "copied {} entries", copied);
+        assert!(copied < 64);
+
+        let q = FlatIndexImpl::new_l2(D).unwrap();
+        let mut other = IVFFlatIndexImpl::new_l2(q, D, 2).unwrap();
+        assert_eq!(
+            index.merge_from(&mut other, Idx::new(0)),
+            Err(Error::IndexMismatch)
+        );
+    }
+
+    #[test]
+    fn ivf_flat_search_preassigned() {
+        let q = FlatIndexImpl::new_l2(D).unwrap();
+        let mut index = IVFFlatIndexImpl::new_l2(q, D, 4).unwrap();
+        let data = some_data();
+        index.train(&data).unwrap();
+        index.add(&data).unwrap();
+        index.set_nprobe(2);
+
+        let query = &data[D as usize * 10..D as usize * 12];
+        let coarse = index.quantizer().unwrap().search(query, 2).unwrap();
+        let result = index
+            .search_preassigned(query, 1, &coarse.labels, &coarse.distances, false)
+            .unwrap();
+        assert_eq!(result.labels, vec![Idx::new(10), Idx::new(11)]);
+        assert_eq!(result, index.search(query, 1).unwrap());
+
+        assert_eq!(
+            index.search_preassigned(query, 1, &coarse.labels[..2], &coarse.distances, false),
+            Err(Error::BadLength)
+        );
+        assert_eq!(
+            index.search_preassigned(&query[1..], 1, &coarse.labels, &coarse.distances, false),
+            Err(Error::BadLength)
+        );
+    }
+
+    #[test]
+    fn ivf_stats() {
+        let q = FlatIndexImpl::new_l2(D).unwrap();
+        let mut index = IVFFlatIndexImpl::new_l2(q, D, 4).unwrap();
+        let data = some_data();
+        index.train(&data).unwrap();
+        index.add(&data).unwrap();
+        index.set_nprobe(2);
+
+        let (result, stats) = with_ivf_stats(|| index.search(&data[..D as usize * 3], 1));
+        assert!(result.is_ok());
+        assert!(stats.nq >= 3);
+        assert!(stats.nlist >= 6);
+        assert!(stats.ndis > 0);
+        assert!(stats.search_time >= 0.);
+    }
+
+    #[test]
+    fn ivf_stats_sub_saturates() {
+        let before = IvfStats {
+            nq: 5,
+            ndis: 100,
+            search_time: 2.,
+            ..Default::default()
+        };
+        let after = IvfStats {
+            nq: 8,
+            search_time: 1.,
+            ..Default::default()
+        };
+        let delta = after - before;
+        assert_eq!(delta.nq, 3);
+        assert_eq!(delta.ndis, 0);
+        assert_eq!(delta.search_time, 0.);
+    }
+
+    #[test]
+    fn index_impl_into_ivf() {
+        let mut index = index_factory(D, "IVF4,Flat", MetricType::L2).unwrap();
+        let data = some_data();
+        index.train(&data).unwrap();
+        index.add(&data).unwrap();
+
+        let mut index = index.into_ivf().unwrap();
+        assert_eq!(index.nlist(), 4);
+        assert_eq!(index.list_sizes().iter().sum::<usize>(), 64);
+        index.make_direct_map(true).unwrap();
+        assert_eq!(index.reconstruct(Idx::new(3)).unwrap(), &data[24..32]);
+        assert_eq!(index.quantizer().unwrap().ntotal(), 4);
+
+        let index = index_factory(D, "Flat", MetricType::L2).unwrap();
+        assert!(index.into_ivf().is_err());
+    }
+}
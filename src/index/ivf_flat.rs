@@ -1,5 +1,6 @@
 //! Interface and implementation to IVFFlat index type.
 
+use super::ivf::IvfIndex;
 use super::*;
 
 use crate::error::Result;
@@ -75,28 +76,35 @@ impl IVFFlatIndexImpl {
     }
 
     /// Get number of probes at query time
+    /// (same as [`IvfIndex::nprobe`])
     pub fn nprobe(&self) -> u32 {
-        unsafe { faiss_IndexIVFFlat_nprobe(self.inner_ptr()) as u32 }
+        IvfIndex::nprobe(self)
     }
 
     /// Set number of probes at query time
+    /// (same as [`IvfIndex::set_nprobe`])
     pub fn set_nprobe(&mut self, value: u32) {
-        unsafe {
-            faiss_IndexIVFFlat_set_nprobe(self.inner_ptr(), value as usize);
-        }
+        IvfIndex::set_nprobe(self, value)
     }
 
     /// Get number of possible key values
+    /// (same as [`IvfIndex::nlist`])
     pub fn nlist(&self) -> u32 {
-        unsafe { faiss_IndexIVFFlat_nlist(self.inner_ptr()) as u32 }
+        IvfIndex::nlist(self)
     }
 
     /// Get train type
+    /// (same as [`IvfIndex::train_type`])
     pub fn train_type(&self) -> Option<TrainType> {
-        unsafe {
-            let code = faiss_IndexIVFFlat_quantizer_trains_alone(self.inner_ptr());
-            TrainType::from_code(code)
-        }
+        IvfIndex::train_type(self)
+    }
+
+    /// Initialize (or reset) the direct map, which is required in order to
+    /// reconstruct vectors from the index.
+    /// If `new_maintain` is false, the direct map is discarded instead.
+    /// (same as [`IvfIndex::make_direct_map`])
+    pub fn make_direct_map(&mut self, new_maintain: bool) -> Result<()> {
+        IvfIndex::make_direct_map(self, new_maintain)
     }
 }
 
@@ -166,12 +174,6 @@ impl IndexImpl {
             }
         }
     }
-
-    pub fn set_nprobe(&mut self, nprobe: usize) {
-        unsafe {
-            faiss_IndexIVFFlat_set_nprobe(self.inner_ptr(), nprobe);
-        }
-    }
 }
 
 #[cfg(test)]
@@ -300,6 +302,33 @@ mod tests {
         assert_eq!(index.ntotal(), 5);
     }
 
+    #[test]
+    fn index_reconstruct() {
+        let q = FlatIndexImpl::new_l2(D).unwrap();
+        let mut index = IVFFlatIndexImpl::new_l2(q, D, 1).unwrap();
+        let some_data = &[
+            7.5_f32, -7.5, 7.5, -7.5, 7.5, 7.5, 7.5, 7.5, -1., 1., 1., 1., 1., 1., 1., -1., 4.,
+            -4., -8., 1., 1., 2., 4., -1., 8., 8., 10., -10., -10., 10., -10., 10., 16., 16., 32.,
+            25., 20., 20., 40., 15.,
+        ];
+        index.train(some_data).unwrap();
+        index.add(some_data).unwrap();
+
+        // no direct map yet
+        assert!(index.reconstruct(Idx::new(1)).is_err());
+
+        index.make_direct_map(true).unwrap();
+        let v = index.reconstruct(Idx::new(1)).unwrap();
+        assert_eq!(&v[..], &some_data[8..16]);
+        let v = index.reconstruct_n(Idx::new(1), 2).unwrap();
+        assert_eq!(&v[..], &some_data[8..24]);
+        let v = index
+            .reconstruct_batch(&[Idx::new(4), Idx::new(0)])
+            .unwrap();
+        assert_eq!(&v[..8], &some_data[32..40]);
+        assert_eq!(&v[8..], &some_data[..8]);
+    }
+
     #[test]
     fn index_upcast() {
         let q = FlatIndexImpl::new_l2(D).unwrap();
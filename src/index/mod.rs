@@ -16,21 +16,28 @@ use crate::metric::MetricType;
 use crate::selector::IdSelector;
 use std::ffi::CString;
 use std::fmt::{self, Display, Formatter, Write};
-use std::os::raw::c_uint;
+use std::os::raw::{c_int, c_uint};
 use std::{mem, ptr};
 
 use faiss_sys::*;
 
 pub mod autotune;
 pub mod flat;
+pub mod flat_1d;
 pub mod id_map;
 pub mod io;
+mod io_envelope;
 pub mod io_flags;
+mod io_inspect;
+mod io_stream;
+pub mod ivf;
 pub mod ivf_flat;
 pub mod lsh;
 pub mod pretransform;
 pub mod refine_flat;
+pub mod replicas;
 pub mod scalar_quantizer;
+pub mod shards;
 
 #[cfg(feature = "gpu")]
 pub mod gpu;
@@ -180,6 +187,36 @@ pub trait Index {
     /// Remove data vectors represented by IDs.
     fn remove_ids(&mut self, sel: &IdSelector) -> Result<usize>;
 
+    /// Compute the residual between the vector `x` and the reconstruction
+    /// of the vector encoded by `key`, as returned by `search` or `assign`.
+    ///
+    /// # Error
+    ///
+    /// Returns `Error::BadLength` if `x` is not a single vector of size `d`.
+    fn compute_residual(&mut self, x: &[f32], key: Idx) -> Result<Vec<f32>>;
+
+    /// Compute the residuals of multiple vectors at once, one vector per key.
+    /// The residuals are returned in a C-contiguous slice of size `xs.len()`.
+    ///
+    /// # Error
+    ///
+    /// Returns `Error::BadLength` if `xs` does not contain exactly
+    /// `keys.len()` vectors.
+    fn compute_residual_n(&mut self, xs: &[f32], keys: &[Idx]) -> Result<Vec<f32>>;
+
+    /// Reconstruct the stored vector with the given key.
+    /// Not all index types may support this operation, and some (such as
+    /// IVF indexes) need a direct map to be made beforehand.
+    fn reconstruct(&self, key: Idx) -> Result<Vec<f32>>;
+
+    /// Reconstruct `n` stored vectors with consecutive keys, starting at `start`.
+    /// The vectors are returned in a C-contiguous slice of size `n * d`.
+    fn reconstruct_n(&self, start: Idx, n: usize) -> Result<Vec<f32>>;
+
+    /// Reconstruct the stored vectors with the given keys.
+    /// The vectors are returned in a C-contiguous slice of size `keys.len() * d`.
+    fn reconstruct_batch(&self, keys: &[Idx]) -> Result<Vec<f32>>;
+
     /// Index verbosity level
     fn verbose(&self) -> bool;
 
@@ -239,6 +276,26 @@ where
         (**self).remove_ids(sel)
     }
 
+    fn compute_residual(&mut self, x: &[f32], key: Idx) -> Result<Vec<f32>> {
+        (**self).compute_residual(x, key)
+    }
+
+    fn compute_residual_n(&mut self, xs: &[f32], keys: &[Idx]) -> Result<Vec<f32>> {
+        (**self).compute_residual_n(xs, keys)
+    }
+
+    fn reconstruct(&self, key: Idx) -> Result<Vec<f32>> {
+        (**self).reconstruct(key)
+    }
+
+    fn reconstruct_n(&self, start: Idx, n: usize) -> Result<Vec<f32>> {
+        (**self).reconstruct_n(start, n)
+    }
+
+    fn reconstruct_batch(&self, keys: &[Idx]) -> Result<Vec<f32>> {
+        (**self).reconstruct_batch(keys)
+    }
+
     fn verbose(&self) -> bool {
         (**self).verbose()
     }
@@ -278,6 +335,23 @@ pub trait ConcurrentIndex: Index {
     /// Perform a ranged search for the vectors closest to the given query vectors
     /// by the given radius.
     fn range_search(&self, q: &[f32], radius: f32) -> Result<RangeSearchResult>;
+
+    /// Compute the residual between the vector `x` and the reconstruction
+    /// of the vector encoded by `key`, as returned by `search` or `assign`.
+    ///
+    /// # Error
+    ///
+    /// Returns `Error::BadLength` if `x` is not a single vector of size `d`.
+    fn compute_residual(&self, x: &[f32], key: Idx) -> Result<Vec<f32>>;
+
+    /// Compute the residuals of multiple vectors at once, one vector per key.
+    /// The residuals are returned in a C-contiguous slice of size `xs.len()`.
+    ///
+    /// # Error
+    ///
+    /// Returns `Error::BadLength` if `xs` does not contain exactly
+    /// `keys.len()` vectors.
+    fn compute_residual_n(&self, xs: &[f32], keys: &[Idx]) -> Result<Vec<f32>>;
 }
 
 impl<CI: ConcurrentIndex> ConcurrentIndex for Box<CI> {
@@ -292,6 +366,14 @@ impl<CI: ConcurrentIndex> ConcurrentIndex for Box<CI> {
     fn range_search(&self, q: &[f32], radius: f32) -> Result<RangeSearchResult> {
         (**self).range_search(q, radius)
     }
+
+    fn compute_residual(&self, x: &[f32], key: Idx) -> Result<Vec<f32>> {
+        (**self).compute_residual(x, key)
+    }
+
+    fn compute_residual_n(&self, xs: &[f32], keys: &[Idx]) -> Result<Vec<f32>> {
+        (**self).compute_residual_n(xs, keys)
+    }
 }
 
 /// Trait for Faiss index types known to be running on the CPU.
@@ -375,7 +457,7 @@ pub struct SearchResult {
 /// The outcome of an index range search operation.
 #[derive(Debug, Clone, PartialEq)]
 pub struct RangeSearchResult {
-    inner: *mut FaissRangeSearchResult,
+    pub(crate) inner: *mut FaissRangeSearchResult,
 }
 
 impl RangeSearchResult {
@@ -477,6 +559,32 @@ impl IndexImpl {
     pub fn inner_ptr(&self) -> *mut FaissIndex {
         self.inner
     }
+
+    pub fn set_nprobe(&mut self, nprobe: usize) {
+        unsafe {
+            faiss_IndexIVFFlat_set_nprobe(self.inner_ptr(), nprobe);
+        }
+    }
+
+    /// Initialize (or reset) the direct map of an IVF index, which is
+    /// required in order to reconstruct vectors from it.
+    ///
+    /// # Error
+    ///
+    /// Returns `Error::BadCast` if the index is not an IVF index.
+    pub fn make_direct_map(&mut self, new_maintain: bool) -> Result<()> {
+        unsafe {
+            let ivf_inner = faiss_IndexIVF_cast(self.inner_ptr());
+            if ivf_inner.is_null() {
+                return Err(Error::BadCast);
+            }
+            faiss_try(faiss_IndexIVF_make_direct_map(
+                ivf_inner,
+                c_int::from(new_maintain),
+            ))?;
+            Ok(())
+        }
+    }
 }
 
 impl NativeIndex for IndexImpl {
@@ -545,6 +653,167 @@ impl TryClone for IndexImpl {
     }
 }
 
+/// A family of index types known to this crate,
+/// as determined at run time with [`IndexImpl::kind`].
+#[derive(Debug, Copy, Clone, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum IndexKind {
+    /// Flat index ([`FlatIndexImpl`](flat::FlatIndexImpl))
+    Flat,
+    /// Flat index for 1-dimensional vectors ([`Flat1DIndex`](flat_1d::Flat1DIndex))
+    Flat1D,
+    /// IVF flat index ([`IVFFlatIndexImpl`](ivf_flat::IVFFlatIndexImpl))
+    IvfFlat,
+    /// IVF scalar quantizer index
+    /// ([`IVFScalarQuantizerIndexImpl`](scalar_quantizer::IVFScalarQuantizerIndexImpl))
+    IvfScalarQuantizer,
+    /// LSH index ([`LshIndex`](lsh::LshIndex))
+    Lsh,
+    /// Scalar quantizer index
+    /// ([`ScalarQuantizerIndexImpl`](scalar_quantizer::ScalarQuantizerIndexImpl))
+    ScalarQuantizer,
+    /// Index with vector transforms
+    /// ([`PreTransformIndexImpl`](pretransform::PreTransformIndexImpl))
+    PreTransform,
+    /// ID-mapped index ([`IdMap`](id_map::IdMap))
+    IdMap,
+    /// ID-mapped index with a reverse map ([`IdMap2`](id_map::IdMap2))
+    IdMap2,
+    /// Index refined by a flat index
+    /// ([`RefineFlatIndexImpl`](refine_flat::RefineFlatIndexImpl))
+    RefineFlat,
+    /// Any other index type
+    Other,
+}
+
+/// An index converted to its statically typed implementation,
+/// as obtained with [`IndexImpl::into_typed`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum TypedIndex {
+    /// Flat index
+    Flat(flat::FlatIndexImpl),
+    /// Flat index for 1-dimensional vectors
+    Flat1D(flat_1d::Flat1DIndex),
+    /// IVF flat index
+    IvfFlat(ivf_flat::IVFFlatIndexImpl),
+    /// IVF scalar quantizer index
+    IvfScalarQuantizer(scalar_quantizer::IVFScalarQuantizerIndexImpl<IndexImpl>),
+    /// LSH index
+    Lsh(lsh::LshIndex),
+    /// Scalar quantizer index
+    ScalarQuantizer(scalar_quantizer::ScalarQuantizerIndexImpl),
+    /// Index with vector transforms
+    PreTransform(pretransform::PreTransformIndexImpl<IndexImpl>),
+    /// ID-mapped index
+    IdMap(id_map::IdMap<IndexImpl>),
+    /// ID-mapped index with a reverse map
+    IdMap2(id_map::IdMap2<IndexImpl>),
+    /// Index refined by a flat index
+    RefineFlat(refine_flat::RefineFlatIndexImpl<IndexImpl>),
+    /// Any other index type, left as is
+    Other(IndexImpl),
+}
+
+impl TypedIndex {
+    /// Retrieve the family of the contained index.
+    pub fn kind(&self) -> IndexKind {
+        match self {
+            TypedIndex::Flat(_) => IndexKind::Flat,
+            TypedIndex::Flat1D(_) => IndexKind::Flat1D,
+            TypedIndex::IvfFlat(_) => IndexKind::IvfFlat,
+            TypedIndex::IvfScalarQuantizer(_) => IndexKind::IvfScalarQuantizer,
+            TypedIndex::Lsh(_) => IndexKind::Lsh,
+            TypedIndex::ScalarQuantizer(_) => IndexKind::ScalarQuantizer,
+            TypedIndex::PreTransform(_) => IndexKind::PreTransform,
+            TypedIndex::IdMap(_) => IndexKind::IdMap,
+            TypedIndex::IdMap2(_) => IndexKind::IdMap2,
+            TypedIndex::RefineFlat(_) => IndexKind::RefineFlat,
+            TypedIndex::Other(_) => IndexKind::Other,
+        }
+    }
+
+    /// Convert the contained index back to the base `IndexImpl` type.
+    pub fn upcast(self) -> IndexImpl {
+        match self {
+            TypedIndex::Flat(index) => index.upcast(),
+            TypedIndex::Flat1D(index) => index.upcast(),
+            TypedIndex::IvfFlat(index) => index.upcast(),
+            TypedIndex::IvfScalarQuantizer(index) => index.upcast(),
+            TypedIndex::Lsh(index) => index.upcast(),
+            TypedIndex::ScalarQuantizer(index) => index.upcast(),
+            TypedIndex::PreTransform(index) => index.upcast(),
+            TypedIndex::IdMap(index) => index.upcast(),
+            TypedIndex::IdMap2(index) => index.upcast(),
+            TypedIndex::RefineFlat(index) => index.upcast(),
+            TypedIndex::Other(index) => index,
+        }
+    }
+}
+
+impl IndexImpl {
+    /// Determine the family of this index at run time.
+    ///
+    /// The outcome tells which of the `into_*` conversion methods
+    /// (or which variant of [`TypedIndex`]) applies to this index.
+    pub fn kind(&self) -> IndexKind {
+        unsafe {
+            let inner = self.inner;
+            if !faiss_IndexFlat1D_cast(inner).is_null() {
+                IndexKind::Flat1D
+            } else if !faiss_IndexFlat_cast(inner).is_null() {
+                IndexKind::Flat
+            } else if !faiss_IndexIVFFlat_cast(inner).is_null() {
+                IndexKind::IvfFlat
+            } else if !faiss_IndexIVFScalarQuantizer_cast(inner).is_null() {
+                IndexKind::IvfScalarQuantizer
+            } else if !faiss_IndexLSH_cast(inner).is_null() {
+                IndexKind::Lsh
+            } else if !faiss_IndexScalarQuantizer_cast(inner).is_null() {
+                IndexKind::ScalarQuantizer
+            } else if !faiss_IndexPreTransform_cast(inner).is_null() {
+                IndexKind::PreTransform
+            } else if !faiss_IndexIDMap2_cast(inner).is_null() {
+                IndexKind::IdMap2
+            } else if !faiss_IndexIDMap_cast(inner).is_null() {
+                IndexKind::IdMap
+            } else if !faiss_IndexRefineFlat_cast(inner).is_null() {
+                IndexKind::RefineFlat
+            } else {
+                IndexKind::Other
+            }
+        }
+    }
+
+    /// Convert this index to its statically typed implementation,
+    /// according to its [kind](IndexImpl::kind).
+    ///
+    /// # Error
+    ///
+    /// This function returns an error if the conversion fails on the native
+    /// side, such as when the reverse ID map of an [`IdMap2`](id_map::IdMap2) index cannot
+    /// be built. The index is consumed in that case.
+    pub fn into_typed(self) -> Result<TypedIndex> {
+        Ok(match self.kind() {
+            IndexKind::Flat => TypedIndex::Flat(self.into_flat()?),
+            IndexKind::Flat1D => TypedIndex::Flat1D(self.into_flat_1d()?),
+            IndexKind::IvfFlat => TypedIndex::IvfFlat(self.into_ivf_flat()?),
+            IndexKind::IvfScalarQuantizer => {
+                TypedIndex::IvfScalarQuantizer(self.into_ivf_scalar_quantizer()?)
+            }
+            IndexKind::Lsh => TypedIndex::Lsh(self.into_lsh()?),
+            IndexKind::ScalarQuantizer => {
+                TypedIndex::ScalarQuantizer(self.into_scalar_quantizer()?)
+            }
+            IndexKind::PreTransform => TypedIndex::PreTransform(self.into_pre_transform()?),
+            IndexKind::IdMap => TypedIndex::IdMap(self.into_id_map()?),
+            IndexKind::IdMap2 => TypedIndex::IdMap2(self.into_id_map2()?),
+            IndexKind::RefineFlat => TypedIndex::RefineFlat(self.into_refine_flat()?),
+            IndexKind::Other => TypedIndex::Other(self),
+        })
+    }
+}
+
 /// Use the index factory to create a native instance of a Faiss index, for `d`-dimensional
 /// vectors. `description` should follow the exact guidelines as the native Faiss interface
 /// (see the [Faiss wiki](https://github.com/facebookresearch/faiss/wiki/Faiss-indexes) for examples).
@@ -574,7 +843,7 @@ where
 
 #[cfg(test)]
 mod tests {
-    use super::{index_factory, Idx, Index, TryClone};
+    use super::{index_factory, Idx, Index, IndexKind, TryClone, TypedIndex};
     use crate::metric::MetricType;
 
     #[test]
@@ -773,4 +1042,45 @@ mod tests {
         assert!(labels == &[Idx(1), Idx(2)] || labels == &[Idx(2), Idx(1)]);
         assert!(distances.iter().all(|x| *x > 0.));
     }
+
+    #[test]
+    fn index_kind() {
+        let cases = [
+            ("Flat", IndexKind::Flat),
+            ("IVF4,Flat", IndexKind::IvfFlat),
+            ("IVF4,SQ8", IndexKind::IvfScalarQuantizer),
+            ("LSH", IndexKind::Lsh),
+            ("SQ8", IndexKind::ScalarQuantizer),
+            ("PCA4,Flat", IndexKind::PreTransform),
+            ("IDMap,Flat", IndexKind::IdMap),
+            ("IDMap2,Flat", IndexKind::IdMap2),
+            ("Flat,RFlat", IndexKind::RefineFlat),
+            ("PQ4", IndexKind::Other),
+        ];
+        for &(description, kind) in &cases {
+            let index = index_factory(8, description, MetricType::L2).unwrap();
+            assert_eq!(index.kind(), kind, "{}", description);
+            let typed = index.into_typed().unwrap();
+            assert_eq!(typed.kind(), kind, "{}", description);
+            assert_eq!(typed.upcast().d(), 8);
+        }
+    }
+
+    #[test]
+    fn into_typed() {
+        let index = index_factory(8, "IDMap,Flat", MetricType::L2).unwrap();
+        match index.into_typed().unwrap() {
+            TypedIndex::IdMap(mut index) => {
+                index.add_with_ids(&[1.; 8], &[Idx::new(42)]).unwrap();
+                assert_eq!(index.ntotal(), 1);
+            }
+            typed => panic!("unexpected index kind {:?}", typed.kind()),
+        }
+
+        let index = index_factory(8, "IDMap2,Flat", MetricType::L2).unwrap();
+        match index.into_typed().unwrap() {
+            TypedIndex::IdMap2(index) => assert_eq!(index.ntotal(), 0),
+            typed => panic!("unexpected index kind {:?}", typed.kind()),
+        }
+    }
 }
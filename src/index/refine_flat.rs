@@ -102,6 +102,23 @@ impl TryFromInnerPtr for RefineFlatIndexImpl<IndexImpl> {
     }
 }
 
+impl IndexImpl {
+    pub fn into_refine_flat(self) -> Result<RefineFlatIndexImpl<IndexImpl>> {
+        unsafe {
+            let new_inner = faiss_IndexRefineFlat_cast(self.inner_ptr());
+            if new_inner.is_null() {
+                Err(Error::BadCast)
+            } else {
+                mem::forget(self);
+                Ok(RefineFlatIndexImpl {
+                    inner: new_inner,
+                    base_index: PhantomData,
+                })
+            }
+        }
+    }
+}
+
 impl<BI> Index for RefineFlatIndexImpl<BI> {
     fn is_trained(&self) -> bool {
         unsafe { faiss_Index_is_trained(self.inner_ptr()) != 0 }
@@ -220,6 +237,81 @@ impl<BI> Index for RefineFlatIndexImpl<BI> {
             faiss_Index_set_verbose(self.inner, std::os::raw::c_int::from(value));
         }
     }
+
+    fn compute_residual(&mut self, x: &[f32], key: Idx) -> Result<Vec<f32>> {
+        let d = self.d() as usize;
+        if x.len() != d {
+            return Err(Error::BadLength);
+        }
+        unsafe {
+            let mut residual = vec![0_f32; d];
+            faiss_try(faiss_Index_compute_residual(
+                self.inner_ptr(),
+                x.as_ptr(),
+                residual.as_mut_ptr(),
+                key.to_native(),
+            ))?;
+            Ok(residual)
+        }
+    }
+
+    fn compute_residual_n(&mut self, xs: &[f32], keys: &[Idx]) -> Result<Vec<f32>> {
+        let d = self.d() as usize;
+        if xs.len() != keys.len() * d {
+            return Err(Error::BadLength);
+        }
+        unsafe {
+            let mut residuals = vec![0_f32; xs.len()];
+            faiss_try(faiss_Index_compute_residual_n(
+                self.inner_ptr(),
+                keys.len() as idx_t,
+                xs.as_ptr(),
+                residuals.as_mut_ptr(),
+                keys.as_ptr() as *const _,
+            ))?;
+            Ok(residuals)
+        }
+    }
+
+    fn reconstruct(&self, key: Idx) -> Result<Vec<f32>> {
+        unsafe {
+            let mut x = vec![0_f32; self.d() as usize];
+            faiss_try(faiss_Index_reconstruct(
+                self.inner_ptr(),
+                key.to_native(),
+                x.as_mut_ptr(),
+            ))?;
+            Ok(x)
+        }
+    }
+
+    fn reconstruct_n(&self, start: Idx, n: usize) -> Result<Vec<f32>> {
+        unsafe {
+            let mut x = vec![0_f32; n * self.d() as usize];
+            faiss_try(faiss_Index_reconstruct_n(
+                self.inner_ptr(),
+                start.to_native(),
+                n as idx_t,
+                x.as_mut_ptr(),
+            ))?;
+            Ok(x)
+        }
+    }
+
+    fn reconstruct_batch(&self, keys: &[Idx]) -> Result<Vec<f32>> {
+        unsafe {
+            let d = self.d() as usize;
+            let mut x = vec![0_f32; keys.len() * d];
+            for (i, key) in keys.iter().enumerate() {
+                faiss_try(faiss_Index_reconstruct(
+                    self.inner_ptr(),
+                    key.to_native(),
+                    x.as_mut_ptr().add(i * d),
+                ))?;
+            }
+            Ok(x)
+        }
+    }
 }
 
 impl<I> TryClone for RefineFlatIndexImpl<I> {
@@ -287,12 +379,50 @@ where
             Ok(RangeSearchResult { inner: p_res })
         }
     }
+
+    fn compute_residual(&self, x: &[f32], key: Idx) -> Result<Vec<f32>> {
+        let d = self.d() as usize;
+        if x.len() != d {
+            return Err(Error::BadLength);
+        }
+        unsafe {
+            let mut residual = vec![0_f32; d];
+            faiss_try(faiss_Index_compute_residual(
+                self.inner,
+                x.as_ptr(),
+                residual.as_mut_ptr(),
+                key.to_native(),
+            ))?;
+            Ok(residual)
+        }
+    }
+
+    fn compute_residual_n(&self, xs: &[f32], keys: &[Idx]) -> Result<Vec<f32>> {
+        let d = self.d() as usize;
+        if xs.len() != keys.len() * d {
+            return Err(Error::BadLength);
+        }
+        unsafe {
+            let mut residuals = vec![0_f32; xs.len()];
+            faiss_try(faiss_Index_compute_residual_n(
+                self.inner,
+                keys.len() as idx_t,
+                xs.as_ptr(),
+                residuals.as_mut_ptr(),
+                keys.as_ptr() as *const _,
+            ))?;
+            Ok(residuals)
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::RefineFlatIndexImpl;
-    use crate::index::{flat::FlatIndexImpl, ConcurrentIndex, Idx, Index, UpcastIndex};
+    use crate::index::{
+        flat::FlatIndexImpl, index_factory, ConcurrentIndex, Idx, Index, UpcastIndex,
+    };
+    use crate::MetricType;
 
     const D: u32 = 8;
 
@@ -349,4 +479,14 @@ mod tests {
         let index_impl = refine.upcast();
         assert_eq!(index_impl.d(), D);
     }
+
+    #[test]
+    fn index_impl_to_refine_flat() {
+        let index = index_factory(D, "Flat,RFlat", MetricType::L2).unwrap();
+        let refine = index.into_refine_flat().unwrap();
+        assert_eq!(refine.d(), D);
+
+        let index = index_factory(D, "Flat", MetricType::L2).unwrap();
+        assert!(index.into_refine_flat().is_err());
+    }
 }
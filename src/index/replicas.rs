@@ -0,0 +1,305 @@
+//! Module for the replicated index wrapper.
+//!
+//! A [`ReplicatedIndex`] holds a number of child indexes (the replicas)
+//! with the same contents. Vectors are added to every replica, whereas
+//! queries are split between them, which is typically used to spread the
+//! search load over several devices.
+//!
+//! The child indexes are owned by the replicated index, and are dropped
+//! along with it unless recovered with [`remove_replica`] or
+//! [`into_replicas`].
+//!
+//! Note that Faiss cannot serialize replicated indexes. In order to persist
+//! one, write its replicas with the functions in [`io`],
+//! and rebuild it from the replicas read back with [`from_replicas`].
+//!
+//! [`ReplicatedIndex`]: struct.ReplicatedIndex.html
+//! [`io`]: ../io/index.html
+//! [`from_replicas`]: struct.ReplicatedIndex.html#method.from_replicas
+//! [`remove_replica`]: struct.ReplicatedIndex.html#method.remove_replica
+//! [`into_replicas`]: struct.ReplicatedIndex.html#method.into_replicas
+//!
+//! # Examples
+//!
+//! ```
+//! use faiss::{FlatIndex, Index};
+//! use faiss::index::replicas::ReplicatedIndex;
+//! # fn run() -> Result<(), Box<dyn std::error::Error>>  {
+//! let mut index = ReplicatedIndex::new(4)?;
+//! index.add_replica(FlatIndex::new_l2(4)?)?;
+//! index.add_replica(FlatIndex::new_l2(4)?)?;
+//! index.add(&[0., 1., 0., 1., 1., 0., 1., 0.])?;
+//! assert_eq!(index.ntotal(), 2);
+//! assert_eq!(index.at(1).unwrap().ntotal(), 2);
+//! # Ok(())
+//! # }
+//! # run().unwrap();
+//! ```
+
+use crate::error::{Error, Result};
+use crate::faiss_try;
+use crate::index::{
+    AssignSearchResult, ConcurrentIndex, CpuIndex, Idx, Index, NativeIndex, RangeSearchResult,
+    SearchResult,
+};
+use crate::selector::IdSelector;
+use faiss_sys::*;
+
+use std::mem;
+use std::os::raw::c_int;
+use std::ptr;
+
+/// Index which replicates its contents over a set of owned indexes.
+///
+/// See the [module level documentation] for more information.
+///
+/// [module level documentation]: ./index.html
+#[derive(Debug)]
+pub struct ReplicatedIndex<I> {
+    inner: *mut FaissIndexReplicas,
+    replicas: Vec<I>,
+}
+
+unsafe impl<I: Send> Send for ReplicatedIndex<I> {}
+unsafe impl<I: Sync> Sync for ReplicatedIndex<I> {}
+impl<I: CpuIndex> CpuIndex for ReplicatedIndex<I> {}
+
+impl<I> NativeIndex for ReplicatedIndex<I> {
+    fn inner_ptr(&self) -> *mut FaissIndex {
+        self.inner
+    }
+}
+
+impl<I> Drop for ReplicatedIndex<I> {
+    fn drop(&mut self) {
+        // the native index does not own the replicas,
+        // which are dropped afterwards along with `self.replicas`
+        unsafe {
+            faiss_IndexReplicas_free(self.inner);
+        }
+    }
+}
+
+impl<I> ReplicatedIndex<I>
+where
+    I: NativeIndex + Index,
+{
+    /// Create an empty replicated index for vectors of dimensionality `d`,
+    /// operating each replica in a separate thread.
+    pub fn new(d: u32) -> Result<Self> {
+        Self::new_with_options(d, true)
+    }
+
+    /// Create an empty replicated index for vectors of dimensionality `d`.
+    ///
+    /// If `threaded` is true, each replica is operated in a separate thread.
+    pub fn new_with_options(d: u32, threaded: bool) -> Result<Self> {
+        unsafe {
+            let mut inner = ptr::null_mut();
+            faiss_try(faiss_IndexReplicas_new_with_options(
+                &mut inner,
+                d as idx_t,
+                c_int::from(threaded),
+            ))?;
+            // replicas are owned on the Rust side
+            faiss_IndexReplicas_set_own_fields(inner, 0);
+            Ok(ReplicatedIndex {
+                inner,
+                replicas: Vec::new(),
+            })
+        }
+    }
+
+    /// Create a replicated index for vectors of dimensionality `d`
+    /// out of the given replicas, with the default options of [`new`].
+    ///
+    /// [`new`]: #method.new
+    pub fn from_replicas<T>(d: u32, replicas: T) -> Result<Self>
+    where
+        T: IntoIterator<Item = I>,
+    {
+        let mut index = Self::new(d)?;
+        for replica in replicas {
+            index.add_replica(replica)?;
+        }
+        Ok(index)
+    }
+
+    /// Add a replica to this index, taking ownership of it.
+    ///
+    /// # Error
+    ///
+    /// Returns `Error::IndexMismatch` if the replica differs from this index
+    /// in dimensionality, or from the existing replicas in metric type,
+    /// in whether it is trained or in the number of vectors it holds.
+    /// The replica is dropped in that case.
+    /// Should Faiss reject the replica nonetheless, it is leaked instead,
+    /// since the native index may still refer to it.
+    pub fn add_replica(&mut self, replica: I) -> Result<()> {
+        // Faiss only validates the replica after registering it,
+        // so a rejected replica must not be dropped
+        let compatible = replica.d() == self.d()
+            && match self.replicas.first() {
+                Some(first) => {
+                    replica.metric_type() == first.metric_type()
+                        && replica.is_trained() == first.is_trained()
+                        && replica.ntotal() == first.ntotal()
+                }
+                None => true,
+            };
+        if !compatible {
+            return Err(Error::IndexMismatch);
+        }
+        unsafe {
+            if let Err(e) = faiss_try(faiss_IndexReplicas_add_replica(
+                self.inner,
+                replica.inner_ptr(),
+            )) {
+                mem::forget(replica);
+                return Err(e.into());
+            }
+        }
+        self.replicas.push(replica);
+        Ok(())
+    }
+
+    /// Remove the replica at position `i` from this index, returning it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `i` is out of bounds.
+    pub fn remove_replica(&mut self, i: usize) -> Result<I> {
+        let replica = self.replicas[i].inner_ptr();
+        unsafe {
+            faiss_try(faiss_IndexReplicas_remove_replica(self.inner, replica))?;
+        }
+        Ok(self.replicas.remove(i))
+    }
+
+    /// Obtain a reference to the replica at position `i`,
+    /// or `None` if out of bounds.
+    pub fn at(&self, i: usize) -> Option<&I> {
+        self.replicas.get(i)
+    }
+
+    /// Obtain the number of replicas in this index.
+    pub fn num_replicas(&self) -> usize {
+        self.replicas.len()
+    }
+
+    /// Discard the replicated index, recovering all of its replicas.
+    pub fn into_replicas(mut self) -> Vec<I> {
+        mem::take(&mut self.replicas)
+    }
+}
+
+impl_native_index!(<I> ReplicatedIndex<I>);
+
+impl_concurrent_index!(<I: ConcurrentIndex> ReplicatedIndex<I>);
+
+#[cfg(test)]
+mod tests {
+    use super::ReplicatedIndex;
+    use crate::error::Error;
+    use crate::index::flat::FlatIndexImpl;
+    use crate::index::io::{read_index_from, write_index_to};
+    use crate::index::{index_factory, ConcurrentIndex, Idx, Index};
+    use crate::MetricType;
+
+    const D: u32 = 4;
+
+    #[test]
+    fn replicated_search() {
+        let mut index = ReplicatedIndex::new(D).unwrap();
+        for _ in 0..2 {
+            index
+                .add_replica(FlatIndexImpl::new_l2(D).unwrap())
+                .unwrap();
+        }
+        assert_eq!(index.num_replicas(), 2);
+
+        let data: Vec<f32> = (0..5).flat_map(|i| vec![i as f32; D as usize]).collect();
+        index.add(&data).unwrap();
+        assert_eq!(index.ntotal(), 5);
+        assert_eq!(index.at(0).unwrap().ntotal(), 5);
+        assert_eq!(index.at(1).unwrap().ntotal(), 5);
+        assert!(index.at(2).is_none());
+
+        let query = [[0.1; D as usize], [3.9; D as usize]].concat();
+        let result = ConcurrentIndex::search(&index, &query, 1).unwrap();
+        assert_eq!(result.labels, vec![Idx::new(0), Idx::new(4)]);
+    }
+
+    #[test]
+    fn replicated_add_remove() {
+        let mut index = ReplicatedIndex::new_with_options(D, false).unwrap();
+        let mut replica = FlatIndexImpl::new_l2(D).unwrap();
+        replica.add(&[1.; D as usize]).unwrap();
+        index.add_replica(replica).unwrap();
+        assert_eq!(index.ntotal(), 1);
+
+        // replicas must hold the same number of vectors
+        assert_eq!(
+            index.add_replica(FlatIndexImpl::new_l2(D).unwrap()),
+            Err(Error::IndexMismatch)
+        );
+        assert_eq!(index.num_replicas(), 1);
+
+        let replica = index.remove_replica(0).unwrap();
+        assert_eq!(replica.ntotal(), 1);
+        assert_eq!(index.num_replicas(), 0);
+        assert!(index.into_replicas().is_empty());
+    }
+
+    #[test]
+    fn replicated_mismatched_replica() {
+        let mut index = ReplicatedIndex::new(D).unwrap();
+        index
+            .add_replica(index_factory(D, "Flat", MetricType::L2).unwrap())
+            .unwrap();
+
+        let replica = index_factory(D + 1, "Flat", MetricType::L2).unwrap();
+        assert_eq!(index.add_replica(replica), Err(Error::IndexMismatch));
+        let replica = index_factory(D, "Flat", MetricType::InnerProduct).unwrap();
+        assert_eq!(index.add_replica(replica), Err(Error::IndexMismatch));
+        let replica = index_factory(D, "IVF1,Flat", MetricType::L2).unwrap();
+        assert!(!replica.is_trained());
+        assert_eq!(index.add_replica(replica), Err(Error::IndexMismatch));
+        assert_eq!(index.num_replicas(), 1);
+
+        // the rejected replicas are gone, but the index is still usable
+        index.add(&[1.; D as usize]).unwrap();
+        let result = index.search(&[1.; D as usize], 1).unwrap();
+        assert_eq!(result.labels, vec![Idx::new(0)]);
+        drop(index);
+    }
+
+    #[test]
+    fn replicated_write_read() {
+        let mut index = ReplicatedIndex::new(D).unwrap();
+        for _ in 0..2 {
+            index
+                .add_replica(FlatIndexImpl::new_l2(D).unwrap())
+                .unwrap();
+        }
+        let data: Vec<f32> = (0..4).flat_map(|i| vec![i as f32; D as usize]).collect();
+        index.add(&data).unwrap();
+
+        let mut bufs = Vec::new();
+        for i in 0..index.num_replicas() {
+            let mut buf = Vec::new();
+            write_index_to(index.at(i).unwrap(), &mut buf).unwrap();
+            bufs.push(buf);
+        }
+        let replicas = bufs.iter().map(|buf| read_index_from(&buf[..]).unwrap());
+        let mut restored = ReplicatedIndex::from_replicas(D, replicas).unwrap();
+        assert_eq!(restored.num_replicas(), 2);
+        assert_eq!(restored.ntotal(), 4);
+
+        let query = [2.1; D as usize];
+        assert_eq!(
+            restored.search(&query, 2).unwrap(),
+            index.search(&query, 2).unwrap()
+        );
+    }
+}
@@ -0,0 +1,342 @@
+//! Module for the sharded index wrapper.
+//!
+//! A [`ShardedIndex`] distributes the indexed vectors over a number of
+//! child indexes (the shards), and merges the results of searching each of
+//! them. Please see the [Faiss wiki] for more information.
+//!
+//! The child indexes are owned by the sharded index, and are dropped along
+//! with it unless recovered with [`remove_shard`] or [`into_shards`].
+//!
+//! Note that Faiss cannot serialize sharded indexes. In order to persist
+//! one, write each of its shards separately with the functions in [`io`],
+//! and rebuild it from the shards read back with [`from_shards`].
+//!
+//! [Faiss wiki]: https://github.com/facebookresearch/faiss/wiki/Indexing-1G-vectors
+//! [`ShardedIndex`]: struct.ShardedIndex.html
+//! [`io`]: ../io/index.html
+//! [`from_shards`]: struct.ShardedIndex.html#method.from_shards
+//! [`remove_shard`]: struct.ShardedIndex.html#method.remove_shard
+//! [`into_shards`]: struct.ShardedIndex.html#method.into_shards
+//!
+//! # Examples
+//!
+//! ```
+//! use faiss::{FlatIndex, Index};
+//! use faiss::index::shards::ShardedIndex;
+//! # fn run() -> Result<(), Box<dyn std::error::Error>>  {
+//! let mut index = ShardedIndex::new(4)?;
+//! index.add_shard(FlatIndex::new_l2(4)?)?;
+//! index.add_shard(FlatIndex::new_l2(4)?)?;
+//! index.add(&[0., 1., 0., 1., 1., 0., 1., 0.])?;
+//! assert_eq!(index.ntotal(), 2);
+//! assert_eq!(index.at(0).unwrap().ntotal(), 1);
+//! # Ok(())
+//! # }
+//! # run().unwrap();
+//! ```
+
+use crate::error::{Error, Result};
+use crate::faiss_try;
+use crate::index::{
+    AssignSearchResult, ConcurrentIndex, CpuIndex, Idx, Index, NativeIndex, RangeSearchResult,
+    SearchResult,
+};
+use crate::selector::IdSelector;
+use faiss_sys::*;
+
+use std::mem;
+use std::os::raw::c_int;
+use std::ptr;
+
+/// Index which distributes its vectors over a set of owned shards.
+///
+/// See the [module level documentation] for more information.
+///
+/// [module level documentation]: ./index.html
+#[derive(Debug)]
+pub struct ShardedIndex<I> {
+    inner: *mut FaissIndexShards,
+    shards: Vec<I>,
+}
+
+unsafe impl<I: Send> Send for ShardedIndex<I> {}
+unsafe impl<I: Sync> Sync for ShardedIndex<I> {}
+impl<I: CpuIndex> CpuIndex for ShardedIndex<I> {}
+
+impl<I> NativeIndex for ShardedIndex<I> {
+    fn inner_ptr(&self) -> *mut FaissIndex {
+        self.inner
+    }
+}
+
+impl<I> Drop for ShardedIndex<I> {
+    fn drop(&mut self) {
+        // the native index does not own the shards,
+        // which are dropped afterwards along with `self.shards`
+        unsafe {
+            faiss_IndexShards_free(self.inner);
+        }
+    }
+}
+
+impl<I> ShardedIndex<I>
+where
+    I: NativeIndex + Index,
+{
+    /// Create an empty sharded index for vectors of dimensionality `d`.
+    ///
+    /// Shards are searched sequentially, and vectors added without
+    /// explicit IDs are given successive IDs across all shards.
+    pub fn new(d: u32) -> Result<Self> {
+        Self::new_with_options(d, false, true)
+    }
+
+    /// Create an empty sharded index for vectors of dimensionality `d`.
+    ///
+    /// If `threaded` is true, each shard is operated in a separate thread.
+    /// See [`set_successive_ids`] for the meaning of `successive_ids`.
+    ///
+    /// [`set_successive_ids`]: #method.set_successive_ids
+    pub fn new_with_options(d: u32, threaded: bool, successive_ids: bool) -> Result<Self> {
+        unsafe {
+            let mut inner = ptr::null_mut();
+            faiss_try(faiss_IndexShards_new_with_options(
+                &mut inner,
+                d as idx_t,
+                c_int::from(threaded),
+                c_int::from(successive_ids),
+            ))?;
+            // shards are owned on the Rust side
+            faiss_IndexShards_set_own_fields(inner, 0);
+            Ok(ShardedIndex {
+                inner,
+                shards: Vec::new(),
+            })
+        }
+    }
+
+    /// Create a sharded index for vectors of dimensionality `d`
+    /// out of the given shards, with the default options of [`new`].
+    ///
+    /// [`new`]: #method.new
+    pub fn from_shards<T>(d: u32, shards: T) -> Result<Self>
+    where
+        T: IntoIterator<Item = I>,
+    {
+        let mut index = Self::new(d)?;
+        for shard in shards {
+            index.add_shard(shard)?;
+        }
+        Ok(index)
+    }
+
+    /// Add a shard to this index, taking ownership of it.
+    ///
+    /// The shard may already contain vectors, which become part of this index.
+    ///
+    /// # Error
+    ///
+    /// Returns `Error::IndexMismatch` if the shard differs from this index
+    /// in dimensionality, or from the existing shards in metric type or
+    /// in whether it is trained. The shard is dropped in that case.
+    /// Should Faiss reject the shard nonetheless, it is leaked instead,
+    /// since the native index may still refer to it.
+    pub fn add_shard(&mut self, shard: I) -> Result<()> {
+        // Faiss only validates the shard after registering it,
+        // so a rejected shard must not be dropped
+        let compatible = shard.d() == self.d()
+            && match self.shards.first() {
+                Some(first) => {
+                    shard.metric_type() == first.metric_type()
+                        && shard.is_trained() == first.is_trained()
+                }
+                None => true,
+            };
+        if !compatible {
+            return Err(Error::IndexMismatch);
+        }
+        unsafe {
+            if let Err(e) = faiss_try(faiss_IndexShards_add_shard(self.inner, shard.inner_ptr())) {
+                mem::forget(shard);
+                return Err(e.into());
+            }
+        }
+        self.shards.push(shard);
+        Ok(())
+    }
+
+    /// Remove the shard at position `i` from this index, returning it.
+    ///
+    /// The vectors held by the shard are no longer part of this index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `i` is out of bounds.
+    pub fn remove_shard(&mut self, i: usize) -> Result<I> {
+        let shard = self.shards[i].inner_ptr();
+        unsafe {
+            faiss_try(faiss_IndexShards_remove_shard(self.inner, shard))?;
+        }
+        Ok(self.shards.remove(i))
+    }
+
+    /// Obtain a reference to the shard at position `i`,
+    /// or `None` if out of bounds.
+    pub fn at(&self, i: usize) -> Option<&I> {
+        self.shards.get(i)
+    }
+
+    /// Obtain the number of shards in this index.
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Check whether vectors added without explicit IDs
+    /// are given successive IDs across all shards.
+    pub fn successive_ids(&self) -> bool {
+        unsafe { faiss_IndexShards_successive_ids(self.inner) != 0 }
+    }
+
+    /// Set whether vectors added without explicit IDs are given successive
+    /// IDs across all shards.
+    ///
+    /// When enabled, the IDs returned by each shard are shifted by the
+    /// number of vectors in the preceding shards. When disabled, vectors
+    /// must be added with explicit IDs.
+    pub fn set_successive_ids(&mut self, value: bool) {
+        unsafe {
+            faiss_IndexShards_set_successive_ids(self.inner, c_int::from(value));
+        }
+    }
+
+    /// Discard the sharded index, recovering all of its shards.
+    pub fn into_shards(mut self) -> Vec<I> {
+        mem::take(&mut self.shards)
+    }
+}
+
+impl_native_index!(<I> ShardedIndex<I>);
+
+impl_concurrent_index!(<I: ConcurrentIndex> ShardedIndex<I>);
+
+#[cfg(test)]
+mod tests {
+    use super::ShardedIndex;
+    use crate::error::Error;
+    use crate::index::flat::FlatIndexImpl;
+    use crate::index::io::{read_index_from, write_index_to};
+    use crate::index::{index_factory, ConcurrentIndex, Idx, Index};
+    use crate::MetricType;
+
+    const D: u32 = 4;
+
+    #[test]
+    fn sharded_search() {
+        let mut index = ShardedIndex::new(D).unwrap();
+        for _ in 0..3 {
+            index.add_shard(FlatIndexImpl::new_l2(D).unwrap()).unwrap();
+        }
+        assert_eq!(index.num_shards(), 3);
+        assert!(index.successive_ids());
+
+        let data: Vec<f32> = (0..6).flat_map(|i| vec![i as f32; D as usize]).collect();
+        index.add(&data).unwrap();
+        assert_eq!(index.ntotal(), 6);
+        assert_eq!(index.at(0).unwrap().ntotal(), 2);
+        assert!(index.at(3).is_none());
+
+        let result = ConcurrentIndex::search(&index, &[3.9; D as usize], 2).unwrap();
+        assert_eq!(result.labels, vec![Idx::new(4), Idx::new(3)]);
+    }
+
+    #[test]
+    fn sharded_add_remove() {
+        let mut index = ShardedIndex::new_with_options(D, true, false).unwrap();
+        assert!(!index.successive_ids());
+        let mut shard = index_factory(D, "IDMap,Flat", MetricType::L2).unwrap();
+        shard
+            .add_with_ids(&[1.; D as usize], &[Idx::new(10)])
+            .unwrap();
+        index.add_shard(shard).unwrap();
+        index
+            .add_shard(index_factory(D, "IDMap,Flat", MetricType::L2).unwrap())
+            .unwrap();
+        assert_eq!(index.ntotal(), 1);
+
+        index
+            .add_with_ids(&[2.; D as usize * 2], &[Idx::new(20), Idx::new(21)])
+            .unwrap();
+        assert_eq!(index.ntotal(), 3);
+
+        let result = index.search(&[1.; D as usize], 1).unwrap();
+        assert_eq!(result.labels, vec![Idx::new(10)]);
+
+        let shard = index.remove_shard(0).unwrap();
+        assert_eq!(shard.ntotal(), 2);
+        assert_eq!(index.num_shards(), 1);
+        assert_eq!(index.ntotal(), 1);
+
+        let shards = index.into_shards();
+        assert_eq!(shards.len(), 1);
+        assert_eq!(shards[0].ntotal(), 1);
+    }
+
+    #[test]
+    fn sharded_bad_shard() {
+        let mut index = ShardedIndex::new(D).unwrap();
+        assert_eq!(
+            index.add_shard(FlatIndexImpl::new_l2(D + 1).unwrap()),
+            Err(Error::IndexMismatch)
+        );
+        assert_eq!(index.num_shards(), 0);
+    }
+
+    #[test]
+    fn sharded_mismatched_shard() {
+        let mut index = ShardedIndex::new(D).unwrap();
+        index
+            .add_shard(index_factory(D, "Flat", MetricType::L2).unwrap())
+            .unwrap();
+        index.add(&[1.; D as usize]).unwrap();
+
+        let shard = index_factory(D, "Flat", MetricType::InnerProduct).unwrap();
+        assert_eq!(index.add_shard(shard), Err(Error::IndexMismatch));
+        let shard = index_factory(D, "IVF1,Flat", MetricType::L2).unwrap();
+        assert!(!shard.is_trained());
+        assert_eq!(index.add_shard(shard), Err(Error::IndexMismatch));
+        assert_eq!(index.num_shards(), 1);
+
+        // the rejected shards are gone, but the index is still usable
+        let result = index.search(&[1.; D as usize], 1).unwrap();
+        assert_eq!(result.labels, vec![Idx::new(0)]);
+        drop(index);
+    }
+
+    #[test]
+    fn sharded_write_read() {
+        let mut index = ShardedIndex::new(D).unwrap();
+        for _ in 0..2 {
+            index.add_shard(FlatIndexImpl::new_l2(D).unwrap()).unwrap();
+        }
+        let data: Vec<f32> = (0..4).flat_map(|i| vec![i as f32; D as usize]).collect();
+        index.add(&data).unwrap();
+
+        let mut bufs = Vec::new();
+        for i in 0..index.num_shards() {
+            let mut buf = Vec::new();
+            write_index_to(index.at(i).unwrap(), &mut buf).unwrap();
+            bufs.push(buf);
+        }
+        let shards = bufs.iter().map(|buf| read_index_from(&buf[..]).unwrap());
+        let mut restored = ShardedIndex::from_shards(D, shards).unwrap();
+        assert_eq!(restored.num_shards(), 2);
+        assert_eq!(restored.ntotal(), 4);
+        assert_eq!(restored.at(1).unwrap().ntotal(), 2);
+
+        let query = [2.1; D as usize];
+        assert_eq!(
+            restored.search(&query, 2).unwrap(),
+            index.search(&query, 2).unwrap()
+        );
+    }
+}
@@ -87,7 +87,10 @@
 #[macro_use]
 mod macros;
 
+pub mod brute_force;
 pub mod cluster;
+pub mod config;
+pub mod distance;
 pub mod error;
 pub mod index;
 pub mod metric;
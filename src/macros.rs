@@ -39,9 +39,12 @@ macro_rules! impl_native_linear_transform {
 }
 
 /// A macro which provides a native index implementation to the given type.
+///
+/// Generic types list their type parameters first,
+/// as in `impl_native_index!(<I> IdMap<I>)`.
 macro_rules! impl_native_index {
-    ($t:ty) => {
-        impl crate::index::Index for $t {
+    (<$($g:ident $(: $b:path)?),*> $t:ty) => {
+        impl<$($g $(: $b)?),*> crate::index::Index for $t {
             fn is_trained(&self) -> bool {
                 unsafe { faiss_Index_is_trained(self.inner_ptr()) != 0 }
             }
@@ -172,14 +175,99 @@ macro_rules! impl_native_index {
                     faiss_Index_set_verbose(self.inner_ptr(), std::os::raw::c_int::from(value));
                 }
             }
+
+            fn compute_residual(&mut self, x: &[f32], key: crate::index::Idx) -> Result<Vec<f32>> {
+                let d = self.d() as usize;
+                if x.len() != d {
+                    return Err(crate::error::Error::BadLength);
+                }
+                unsafe {
+                    let mut residual = vec![0_f32; d];
+                    faiss_try(faiss_Index_compute_residual(
+                        self.inner_ptr(),
+                        x.as_ptr(),
+                        residual.as_mut_ptr(),
+                        key.to_native(),
+                    ))?;
+                    Ok(residual)
+                }
+            }
+
+            fn compute_residual_n(
+                &mut self,
+                xs: &[f32],
+                keys: &[crate::index::Idx],
+            ) -> Result<Vec<f32>> {
+                let d = self.d() as usize;
+                if xs.len() != keys.len() * d {
+                    return Err(crate::error::Error::BadLength);
+                }
+                unsafe {
+                    let mut residuals = vec![0_f32; xs.len()];
+                    faiss_try(faiss_Index_compute_residual_n(
+                        self.inner_ptr(),
+                        keys.len() as idx_t,
+                        xs.as_ptr(),
+                        residuals.as_mut_ptr(),
+                        keys.as_ptr() as *const _,
+                    ))?;
+                    Ok(residuals)
+                }
+            }
+
+            fn reconstruct(&self, key: crate::index::Idx) -> Result<Vec<f32>> {
+                unsafe {
+                    let mut x = vec![0_f32; self.d() as usize];
+                    faiss_try(faiss_Index_reconstruct(
+                        self.inner_ptr(),
+                        key.to_native(),
+                        x.as_mut_ptr(),
+                    ))?;
+                    Ok(x)
+                }
+            }
+
+            fn reconstruct_n(&self, start: crate::index::Idx, n: usize) -> Result<Vec<f32>> {
+                unsafe {
+                    let mut x = vec![0_f32; n * self.d() as usize];
+                    faiss_try(faiss_Index_reconstruct_n(
+                        self.inner_ptr(),
+                        start.to_native(),
+                        n as idx_t,
+                        x.as_mut_ptr(),
+                    ))?;
+                    Ok(x)
+                }
+            }
+
+            fn reconstruct_batch(&self, keys: &[crate::index::Idx]) -> Result<Vec<f32>> {
+                unsafe {
+                    let d = self.d() as usize;
+                    let mut x = vec![0_f32; keys.len() * d];
+                    for (i, key) in keys.iter().enumerate() {
+                        faiss_try(faiss_Index_reconstruct(
+                            self.inner_ptr(),
+                            key.to_native(),
+                            x.as_mut_ptr().add(i * d),
+                        ))?;
+                    }
+                    Ok(x)
+                }
+            }
         }
     };
+    ($t:ty) => {
+        impl_native_index!(<> $t);
+    };
 }
 
 /// A macro which provides a concurrent index implementation to the given type.
+///
+/// Generic types list their type parameters first, along with their bounds,
+/// as in `impl_concurrent_index!(<I: ConcurrentIndex> IdMap<I>)`.
 macro_rules! impl_concurrent_index {
-    ($t:ty) => {
-        impl crate::index::ConcurrentIndex for $t
+    (<$($g:ident $(: $b:path)?),*> $t:ty) => {
+        impl<$($g $(: $b)?),*> crate::index::ConcurrentIndex for $t
         where
             Self: crate::index::Index + crate::index::NativeIndex,
         {
@@ -230,6 +318,48 @@ macro_rules! impl_concurrent_index {
                     Ok(RangeSearchResult { inner: p_res })
                 }
             }
+
+            fn compute_residual(&self, x: &[f32], key: crate::index::Idx) -> Result<Vec<f32>> {
+                let d = self.d() as usize;
+                if x.len() != d {
+                    return Err(crate::error::Error::BadLength);
+                }
+                unsafe {
+                    let mut residual = vec![0_f32; d];
+                    faiss_try(faiss_Index_compute_residual(
+                        self.inner_ptr(),
+                        x.as_ptr(),
+                        residual.as_mut_ptr(),
+                        key.to_native(),
+                    ))?;
+                    Ok(residual)
+                }
+            }
+
+            fn compute_residual_n(
+                &self,
+                xs: &[f32],
+                keys: &[crate::index::Idx],
+            ) -> Result<Vec<f32>> {
+                let d = self.d() as usize;
+                if xs.len() != keys.len() * d {
+                    return Err(crate::error::Error::BadLength);
+                }
+                unsafe {
+                    let mut residuals = vec![0_f32; xs.len()];
+                    faiss_try(faiss_Index_compute_residual_n(
+                        self.inner_ptr(),
+                        keys.len() as idx_t,
+                        xs.as_ptr(),
+                        residuals.as_mut_ptr(),
+                        keys.as_ptr() as *const _,
+                    ))?;
+                    Ok(residuals)
+                }
+            }
         }
     };
+    ($t:ty) => {
+        impl_concurrent_index!(<> $t);
+    };
 }
@@ -43,6 +43,11 @@ impl IdSelector {
         })
     }
 
+    /// Check whether the given ID is selected
+    pub fn is_member(&self, idx: Idx) -> bool {
+        unsafe { faiss_IDSelector_is_member(self.inner, idx.to_native()) != 0 }
+    }
+
     /// Return the inner pointer
     pub fn inner_ptr(&self) -> *mut FaissIDSelector {
         self.inner
@@ -59,3 +64,27 @@ impl Drop for IdSelector {
 
 unsafe impl Send for IdSelector {}
 unsafe impl Sync for IdSelector {}
+
+#[cfg(test)]
+mod tests {
+    use super::IdSelector;
+    use crate::index::Idx;
+
+    #[test]
+    fn range_is_member() {
+        let sel = IdSelector::range(Idx::new(2), Idx::new(5)).unwrap();
+        assert!(!sel.is_member(Idx::new(1)));
+        assert!(sel.is_member(Idx::new(2)));
+        assert!(sel.is_member(Idx::new(4)));
+        assert!(!sel.is_member(Idx::new(5)));
+    }
+
+    #[test]
+    fn batch_is_member() {
+        let sel = IdSelector::batch(&[Idx::new(3), Idx::new(8), Idx::new(12)]).unwrap();
+        assert!(sel.is_member(Idx::new(3)));
+        assert!(sel.is_member(Idx::new(12)));
+        assert!(!sel.is_member(Idx::new(4)));
+        assert!(!sel.is_member(Idx::new(0)));
+    }
+}